@@ -1,196 +1,5046 @@
+use std::collections::{HashMap, HashSet};
+
+use gloo::events::EventListener;
+use gloo::file::callbacks::FileReader;
+use gloo::file::{Blob, File};
+use gloo::storage::{LocalStorage, Storage};
+use gloo::timers::callback::{Interval, Timeout};
 use serde::{Deserialize, Serialize};
-use web_sys::HtmlInputElement;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{
+    DataTransfer, Element, HtmlAudioElement, HtmlElement, HtmlImageElement, HtmlInputElement, HtmlTextAreaElement,
+    IntersectionObserver, IntersectionObserverEntry, KeyboardEvent,
+    Notification,
+    NotificationOptions,
+    ScrollBehavior, ScrollIntoViewOptions,
+};
+use yew::context::ContextHandle;
 use yew::prelude::*;
 use yew_agent::{Bridge, Bridged};
+use yew_router::prelude::*;
+
+use crate::services::event_bus::{EventBus, Frame};
+use crate::services::gif::{self, Gif};
+use crate::services::platform::{self, copy_to_clipboard};
+use crate::components::renderers::{
+    default_renderers, mentions_everyone, render_message, render_plain, MessageRenderer,
+};
+use crate::components::settings::Settings as SettingsPanel;
+use crate::services::search::SearchIndex;
+use crate::services::settings::{ClockFormat, NotifyLevel, SendKey, ThemeMode};
+use crate::config::ChatConfig;
+use crate::theme::Theme;
+use crate::services::websocket::{ConnectionState, RawFrame, WebsocketService, WsError, WS_URL};
+use crate::{Route, SettingsContext, User};
 
-use crate::services::event_bus::EventBus;
-use crate::{services::websocket::WebsocketService, User};
+const QUICK_REACTIONS: [&str; 4] = ["👍", "❤️", "😂", "😮"];
+const PICKER_EMOJI: [&str; 24] = [
+    "😀", "😂", "😊", "😍", "😎", "🤔", "😮", "😢",
+    "😡", "🥳", "😴", "🤯", "👍", "👎", "👏", "🙏",
+    "💪", "👋", "❤️", "🔥", "✨", "🎉", "💀", "🦇",
+];
+// Must match the picker's `grid-cols-8`.
+const PICKER_COLUMNS: usize = 8;
+const BLOCKED_STORAGE_KEY: &str = "yewchat.blocked";
+const HISTORY_STORAGE_KEY: &str = "yewchat.history";
+const DRAFTS_STORAGE_KEY: &str = "yewchat.drafts";
+const LAST_READ_STORAGE_KEY: &str = "yewchat.last_read";
+const DISMISSED_STORAGE_KEY: &str = "yewchat.dismissed_announcements";
+const DEFAULT_SIDEBAR_USERS: usize = 50;
+const DEFAULT_COLLAPSE_CHARS: usize = 500;
+// Guards against reply loops when walking a chain of quotes.
+const MAX_REPLY_DEPTH: usize = 20;
+pub const DEFAULT_ROOM: &str = "general";
 
+type MessageId = String;
+type Emoji = String;
+type ObserverCallback = Closure<dyn FnMut(js_sys::Array, IntersectionObserver)>;
+
+#[allow(clippy::enum_variant_names)]
 pub enum Msg {
-    HandleMsg(String),
+    HandleMsg(Frame),
     SubmitMessage,
+    DraftChanged(String),
+    SaveDraft,
+    MentionChanged(Option<MentionToken>),
+    MentionKey(String),
+    ComposerUndo,
+    ComposerRedo,
+    PickMention(String),
+    TogglePreview,
+    AckTimeout(MessageId),
+    ActivityExpired(String),
+    React(MessageId, Emoji),
+    FlushReactions,
+    FlushUsers,
+    HoverMessage(Option<MessageId>),
+    QuickReact(usize),
+    FileSelected(Vec<File>),
+    AttachmentLoaded(u32, Attachment),
+    RemoveAttachment(usize),
+    ToggleRename,
+    SubmitRename,
+    ToggleBlock(String),
+    StartReply(MessageId),
+    StartEdit(MessageId),
+    SubmitEdit,
+    CancelEdit,
+    EditingExpired(MessageId),
+    CancelReply,
+    OpenThread(MessageId),
+    CloseThread,
+    SubmitThreadReply,
+    ScrollToMessage(MessageId),
+    ClearHighlight,
+    // From a `?msg=` permalink; waits until the message is on screen.
+    HighlightMessage(MessageId),
+    OpenMessageMenu(MessageId, i32, i32),
+    CloseMessageMenu,
+    CopyMessageLink(MessageId),
+    MessageLinkCopied(bool),
+    ToggleSettings,
+    SettingsChanged(SettingsContext),
+    ExportHistory,
+    CopyInvite,
+    InviteCopied(bool),
+    SwitchRoom(String),
+    SubmitNewRoom,
+    CycleRoom(isize),
+    ToggleRoomSwitcher,
+    ToggleFocusMode,
+    ToggleTraffic,
+    RefreshTraffic,
+    ExitFocusMode,
+    FilterRoomSwitcher(String),
+    Search(String),
+    ConnectionChanged(usize, ConnectionState),
+    RetryConnection,
+    ReconnectNow(usize),
+    CountdownTick,
+    VisibilityChanged(bool),
+    DismissUnread,
+    Interacted,
+    DismissToast(u32),
+    DismissAnnouncement(String),
+    OnlineChanged(bool),
+    FilterUsers(String),
+    ShowMoreUsers,
+    ToggleShowOffline,
+    ToggleStats,
+    SortStats(StatsSort),
+    CycleRoomNotify(String),
+    ToggleExpanded(MessageId),
+    LoadMedia(MessageId),
+    LoadOlder,
+    ToggleContext(MessageId),
+    ToggleGifPicker,
+    ToggleEmojiPicker,
+    EmojiPickerKey(String),
+    InsertEmoji(&'static str),
+    SearchGifs,
+    GifsLoaded(String, Result<Vec<Gif>, String>),
+    SendGif(String),
 }
 
-#[derive(Deserialize)]
+// Notable lifecycle events, for integrators who want diagnostics.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChatEvent {
+    Connected { server: String },
+    Reconnecting { server: String, attempt: u32 },
+    ConnectionFailed { server: String },
+    MessageSent { room: String },
+    MessageReceived { room: String, from: String },
+    FrameRejected { server: String, reason: String },
+}
+
+fn default_room() -> String {
+    DEFAULT_ROOM.into()
+}
+
+fn default_bubble_width() -> Classes {
+    classes!("max-w-[70%]")
+}
+
+fn default_servers() -> Vec<String> {
+    vec![WS_URL.into()]
+}
+
+#[derive(Properties, PartialEq)]
+pub struct ChatProps {
+    #[prop_or_default]
+    pub theme: Theme,
+    #[prop_or_else(default_room)]
+    pub room: String,
+    #[prop_or_default]
+    pub reconnect_jitter: bool,
+    // The first server is the primary: chat messages are posted there and
+    // the others are expected to relay them.
+    #[prop_or_else(default_servers)]
+    pub servers: Vec<String>,
+    // Sidebar rows rendered at once; the rest are revealed a page at a time.
+    #[prop_or(DEFAULT_SIDEBAR_USERS)]
+    pub max_sidebar_users: usize,
+    // Longer messages are cut off behind a "Show more" toggle.
+    #[prop_or(DEFAULT_COLLAPSE_CHARS)]
+    pub collapse_after_chars: usize,
+    // Width cap for message bubbles, e.g. `max-w-prose` on wide screens.
+    #[prop_or_else(default_bubble_width)]
+    pub bubble_max_width: Classes,
+    // Ask the server for what we missed after a reconnect.
+    #[prop_or_default]
+    pub backfill_history: bool,
+    // Load older messages on scrolling up, for servers that answer
+    // `history` frames carrying `before`.
+    #[prop_or_default]
+    pub paginate_history: bool,
+    #[prop_or_default]
+    pub on_event: Callback<ChatEvent>,
+    // Only for servers that answer every message with an `ack` frame.
+    #[prop_or_default]
+    pub delivery_receipts: bool,
+    // Replaces the arrow icon inside the send button; clicking still sends.
+    #[prop_or_default]
+    pub send_button: Option<Html>,
+}
+
+#[derive(Clone)]
+pub struct Attachment {
+    name: String,
+    mime_type: String,
+    size: u64,
+    data_url: String,
+}
+
+// Also the format of persisted and exported history, so everything the view
+// relies on (including `time`) has to survive a round trip.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
 struct MessageData {
+    #[serde(default)]
+    id: MessageId,
     from: String,
     message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<f64>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    reactions: HashMap<Emoji, Vec<String>>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    repeats: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<MessageId>,
+    // The part of the original the reply is about, when not all of it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    quote: Option<String>,
+    // Replies in a thread point at its root and stay out of the main timeline.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    thread_id: Option<MessageId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    room: Option<String>,
+    // The server this message arrived from; set locally, never sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    origin: Option<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    edited: bool,
+    // Describes the file when `message` is an attachment's data URL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attachment: Option<AttachmentMeta>,
+    // System lines are made locally and never persisted.
+    #[serde(skip)]
+    kind: MessageKind,
+    // Why a system line is there, e.g. the parse error behind an
+    // unreadable message; shown on hover.
+    #[serde(skip)]
+    detail: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AttachmentMeta {
+    name: String,
+    mime_type: String,
+    size: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum MessageKind {
+    #[default]
+    User,
+    // Joins, leaves and unreadable-frame placeholders; `message` is the text.
+    System,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Delivery {
+    Sending,
+    Sent,
+    Failed,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum StatsSort {
+    Count,
+    Name,
+}
+
+// The `@partial` word under the caret, as byte offsets into the draft.
+#[derive(Clone, PartialEq)]
+pub struct MentionToken {
+    start: usize,
+    end: usize,
+    query: String,
+}
+
+impl MentionToken {
+    // `caret` is in UTF-16 units, as the DOM reports it.
+    fn at_caret(text: &str, caret: u32) -> Option<Self> {
+        let mut units = 0;
+        let end = text
+            .char_indices()
+            .find(|(_, c)| {
+                let found = units >= caret as usize;
+                units += c.len_utf16();
+                found
+            })
+            .map_or(text.len(), |(i, _)| i);
+        let before = &text[..end];
+        let start = before.rfind('@')?;
+        let query = &before[start + 1..];
+        let word_start = before[..start].chars().next_back().is_none_or(char::is_whitespace);
+        if !word_start || query.contains(char::is_whitespace) {
+            return None;
+        }
+        Some(Self {
+            start,
+            end,
+            query: query.into(),
+        })
+    }
+}
+
+struct Toast {
+    id: u32,
+    text: String,
+    success: bool,
+    _timeout: Timeout,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+impl MessageData {
+    // Servers that don't assign ids still stamp every message, and the
+    // sender/time pair is the same on every client.
+    fn ensure_id(&mut self) {
+        if self.id.is_empty() {
+            self.id = format!("{}-{}", self.from, self.time.unwrap_or_default());
+        }
+    }
+
+    fn room(&self) -> &str {
+        self.room.as_deref().unwrap_or(DEFAULT_ROOM)
+    }
+
+    // A copy without reactions (like our own echo) says nothing about them;
+    // one with reactions is the server's current state, removals included.
+    fn merge_reactions(&mut self, reactions: HashMap<Emoji, Vec<String>>) {
+        if !reactions.is_empty() {
+            self.reactions = reactions;
+        }
+    }
+
+    fn is_repeat_of(&self, other: &MessageData) -> bool {
+        fn normalize(s: &str) -> String {
+            s.split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+                .to_lowercase()
+        }
+        self.from == other.from
+            && self.room() == other.room()
+            && normalize(&self.message) == normalize(&other.message)
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReactionData {
+    message_id: MessageId,
+    emoji: Emoji,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<String>,
+    // Takes back an earlier reaction with the same emoji.
+    #[serde(default, skip_serializing_if = "is_false")]
+    remove: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ActivityKind {
+    Typing,
+    Uploading,
+    Recording,
+}
+
+impl ActivityKind {
+    fn label(self) -> &'static str {
+        match self {
+            ActivityKind::Typing => "is typing…",
+            ActivityKind::Uploading => "is uploading a file…",
+            ActivityKind::Recording => "is recording audio…",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct ActivityData {
+    kind: ActivityKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EditData {
+    message_id: MessageId,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EditingData {
+    message_id: MessageId,
+    active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReadData {
+    message_id: MessageId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct RenameResult {
+    name: String,
+    accepted: bool,
+    reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum MsgTypes {
     Users,
     Register,
+    #[default]
     Message,
+    Ping,
+    Pong,
+    Reaction,
+    Rename,
+    Join,
+    Leave,
+    Ack,
+    Activity,
+    Edit,
+    Editing,
+    History,
+    Read,
+    Announcement,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct WebSocketMessage {
     message_type: MsgTypes,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<MessageId>,
     data_array: Option<Vec<String>>,
     data: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reply_to: Option<MessageId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    quote: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    thread_id: Option<MessageId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    room: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attachment: Option<AttachmentMeta>,
+    // On acks from servers that count who the message went out to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    recipients: Option<usize>,
+    // History older than this, and how much of it; echoed back with the page.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    before: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    limit: Option<usize>,
+    // Set on `users` frames from servers that also list offline users.
+    #[serde(default, skip_serializing_if = "is_false")]
+    includes_offline: bool,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Role {
+    Admin,
+    #[serde(alias = "moderator")]
+    Mod,
+    #[serde(other)]
+    Member,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UserDetails {
+    nick: String,
+    joined_at: Option<f64>,
+    role: Option<Role>,
+    // Only trusted when the frame says offline users are included.
+    #[serde(default)]
+    online: Option<bool>,
+    #[serde(default)]
+    last_seen: Option<f64>,
+}
+
+// A server frame decoded into what it says, before any of it is applied.
+// Kept free of component state so it can be checked without a browser.
+enum Incoming {
+    Users { names: Vec<String>, details: Vec<UserDetails> },
+    Message(Box<MessageData>),
+    // A message frame whose payload didn't parse; `reason` says why.
+    Unreadable { room: Option<String>, reason: String },
+    Announcement { id: String, text: String },
+    // Entries that didn't parse are kept as errors so they can be logged.
+    History { before: Option<f64>, room: Option<String>, entries: Vec<Result<MessageData, String>> },
+    Read { message_id: MessageId, from: String, time: Option<f64> },
+    Rename(RenameResult),
+    Reaction { message_id: MessageId, emoji: Emoji, from: String, remove: bool },
+    Activity { kind: ActivityKind, from: String, room: Option<String> },
+    Edit { message_id: MessageId, message: String, from: String },
+    Editing { message_id: MessageId, active: bool, from: String },
+    Ack { id: MessageId, recipients: Option<usize> },
+    Pong { sent_at: f64 },
+    // Frame types the client only ever sends.
+    Ignored,
+}
+
+#[derive(Debug, PartialEq)]
+enum FrameError {
+    // Not a frame at all; reported through `ChatEvent::FrameRejected`.
+    Malformed(String),
+    // A frame without what its type needs, e.g. a reaction with no sender.
+    Incomplete(&'static str),
+}
+
+fn payload<T: serde::de::DeserializeOwned>(data: Option<String>, what: &'static str) -> Result<T, FrameError> {
+    data.and_then(|d| serde_json::from_str(&d).ok()).ok_or(FrameError::Incomplete(what))
+}
+
+fn decode_frame(raw: &str) -> Result<Incoming, FrameError> {
+    let msg: WebSocketMessage = serde_json::from_str(raw).map_err(|e| FrameError::Malformed(e.to_string()))?;
+    let incoming = match msg.message_type {
+        MsgTypes::Users => {
+            // Servers that know more about their users send it as a
+            // JSON array in `data`; plain servers only send names.
+            let mut details: Vec<UserDetails> = msg
+                .data
+                .and_then(|d| serde_json::from_str(&d).ok())
+                .unwrap_or_default();
+            if !msg.includes_offline {
+                for detail in &mut details {
+                    detail.online = None;
+                }
+            }
+            Incoming::Users {
+                names: msg.data_array.unwrap_or_default(),
+                details,
+            }
+        }
+        MsgTypes::Message => match msg.data.as_deref().map(serde_json::from_str::<MessageData>) {
+            Some(Ok(mut message_data)) => {
+                message_data.ensure_id();
+                Incoming::Message(Box::new(message_data))
+            }
+            Some(Err(e)) => Incoming::Unreadable {
+                room: msg.room,
+                reason: e.to_string(),
+            },
+            None => Incoming::Unreadable {
+                room: msg.room,
+                reason: "message frame without data".to_string(),
+            },
+        },
+        MsgTypes::Announcement => {
+            let text = match msg.data {
+                Some(text) if !text.trim().is_empty() => text,
+                _ => return Err(FrameError::Incomplete("announcement without text")),
+            };
+            // Without an id the text itself identifies the announcement.
+            Incoming::Announcement {
+                id: msg.id.unwrap_or_else(|| text.clone()),
+                text,
+            }
+        }
+        MsgTypes::History => Incoming::History {
+            before: msg.before,
+            room: msg.room,
+            entries: msg
+                .data_array
+                .unwrap_or_default()
+                .iter()
+                .map(|raw| {
+                    serde_json::from_str::<MessageData>(raw)
+                        .map(|mut message_data| {
+                            message_data.ensure_id();
+                            message_data
+                        })
+                        .map_err(|e| e.to_string())
+                })
+                .collect(),
+        },
+        MsgTypes::Read => match payload(msg.data, "read receipt without a reader")? {
+            ReadData { message_id, from: Some(from), time } => Incoming::Read { message_id, from, time },
+            _ => return Err(FrameError::Incomplete("read receipt without a reader")),
+        },
+        MsgTypes::Rename => Incoming::Rename(payload(msg.data, "rename without a result")?),
+        MsgTypes::Reaction => match payload(msg.data, "reaction without a sender")? {
+            ReactionData { message_id, emoji, from: Some(from), remove } => {
+                Incoming::Reaction { message_id, emoji, from, remove }
+            }
+            _ => return Err(FrameError::Incomplete("reaction without a sender")),
+        },
+        MsgTypes::Activity => match payload(msg.data, "activity without a sender")? {
+            ActivityData { kind, from: Some(from) } => Incoming::Activity { kind, from, room: msg.room },
+            _ => return Err(FrameError::Incomplete("activity without a sender")),
+        },
+        MsgTypes::Edit => match payload(msg.data, "edit without a sender")? {
+            EditData { message_id, message, from: Some(from) } => Incoming::Edit { message_id, message, from },
+            _ => return Err(FrameError::Incomplete("edit without a sender")),
+        },
+        MsgTypes::Editing => match payload(msg.data, "editing marker without a sender")? {
+            EditingData { message_id, active, from: Some(from) } => Incoming::Editing { message_id, active, from },
+            _ => return Err(FrameError::Incomplete("editing marker without a sender")),
+        },
+        MsgTypes::Ack => match msg.data.or(msg.id) {
+            Some(id) => Incoming::Ack {
+                id,
+                recipients: msg.recipients,
+            },
+            None => return Err(FrameError::Incomplete("ack without an id")),
+        },
+        // The server echoes back the timestamp we put in the ping.
+        MsgTypes::Pong => match msg.data.and_then(|d| d.parse::<f64>().ok()) {
+            Some(sent_at) => Incoming::Pong { sent_at },
+            None => return Err(FrameError::Incomplete("pong without a timestamp")),
+        },
+        _ => Incoming::Ignored,
+    };
+    Ok(incoming)
+}
+
+// Work left over from `handle_frame` that needs the component's link.
+enum FrameCommand {
+    Emit(ChatEvent),
+    Toast(String),
+    // (Re)starts the timer that ends a piece of state the frame set up.
+    Arm(Timer),
+}
+
+enum Timer {
+    UsersDebounce,
+    ReactionDigest,
+    Activity(String, ActivityKind),
+    Editing(MessageId),
+}
+
+#[derive(Clone, PartialEq)]
 struct UserProfile {
     name: String,
+    origin: String,
     avatar: String,
+    // Swapped in when the avatar fails to load.
+    fallback_avatar: String,
+    joined_at: Option<f64>,
+    role: Option<Role>,
+    online: bool,
+    // From the server when it knows, otherwise when we saw them go offline.
+    last_seen: Option<f64>,
 }
 
-pub struct Chat {
-    users: Vec<UserProfile>,
-    chat_input: NodeRef,
-    _producer: Box<dyn Bridge<EventBus>>,
-    wss: WebsocketService,
-    messages: Vec<MessageData>,
+impl UserProfile {
+    fn new(name: &str, config: &ChatConfig) -> Self {
+        Self {
+            name: name.into(),
+            origin: String::new(),
+            avatar: config.avatar_url(name),
+            fallback_avatar: initials_avatar(name),
+            joined_at: None,
+            role: None,
+            online: true,
+            last_seen: None,
+        }
+    }
 }
-impl Component for Chat {
-    type Message = Msg;
-    type Properties = ();
 
-    fn create(ctx: &Context<Self>) -> Self {
-        let (user, _) = ctx
-            .link()
-            .context::<User>(Callback::noop())
-            .expect("context to be set");
-        let wss = WebsocketService::new();
-        let username = user.username.borrow().clone();
+// The live users list wins; otherwise the profile remembered when the
+// sender's first message arrived, which also covers messages that beat the
+// first users frame.
+fn sender_profile<'a>(
+    users: &'a [UserProfile],
+    profiles: &'a HashMap<String, UserProfile>,
+    m: &MessageData,
+) -> Option<&'a UserProfile> {
+    users
+        .iter()
+        .find(|u| u.name == m.from && m.origin.as_ref().is_none_or(|o| *o == u.origin))
+        .or_else(|| profiles.get(&m.from))
+}
 
-        let message = WebSocketMessage {
-            message_type: MsgTypes::Register,
-            data: Some(username.to_string()),
-            data_array: None,
-        };
+// `recipients` is the count taken when the message went out, or the exact
+// one from an ack; it shows whether or not the server acks at all.
+fn delivery_note(status: Option<Delivery>, recipients: Option<usize>) -> Option<String> {
+    match (status, recipients) {
+        (Some(Delivery::Sending), _) => Some("Sending…".into()),
+        (Some(Delivery::Failed), _) => Some("Not delivered".into()),
+        (_, Some(n)) if n > 0 => Some(format!("✓ Delivered to {}", n)),
+        (Some(Delivery::Sent), _) => Some("✓ Sent".into()),
+        (None, _) => None,
+    }
+}
 
-        if let Ok(_) = wss
-            .tx
-            .clone()
-            .try_send(serde_json::to_string(&message).unwrap())
-        {
-            log::debug!("message sent successfully");
+// Stable per user, so the same person gets the same color everywhere.
+fn user_color(name: &str) -> String {
+    let hash = name.bytes().fold(0u32, |h, b| h.wrapping_mul(31).wrapping_add(b.into()));
+    format!("hsl({}, 55%, 45%)", hash % 360)
+}
+
+fn initials_avatar(name: &str) -> String {
+    let initial = match name.chars().next() {
+        Some(c) if c.is_alphanumeric() => c.to_uppercase().to_string(),
+        _ => "?".into(),
+    };
+    let svg = format!(
+        "<svg xmlns='http://www.w3.org/2000/svg' viewBox='0 0 40 40'><rect width='40' height='40' fill='{}'/><text x='50%' y='50%' dy='.35em' text-anchor='middle' font-family='sans-serif' font-size='20' fill='white'>{}</text></svg>",
+        user_color(name),
+        initial
+    );
+    format!("data:image/svg+xml,{}", encode_uri_component(&svg))
+}
+
+// Same output as JavaScript's `encodeURIComponent`.
+fn encode_uri_component(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'!' | b'~' | b'*' | b'\'' | b'(' | b')' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
         }
+    }
+    encoded
+}
 
-        Self {
-            users: vec![],
-            messages: vec![],
-            chat_input: NodeRef::default(),
-            wss,
-            _producer: EventBus::bridge(ctx.link().callback(Msg::HandleMsg)),
+fn avatar_fallback(profile: &UserProfile) -> Callback<Event> {
+    let fallback = profile.fallback_avatar.clone();
+    Callback::from(move |e: Event| {
+        let img: HtmlImageElement = e.target_unchecked_into();
+        if img.src() != fallback {
+            img.set_src(&fallback);
         }
+    })
+}
+
+fn role_badge(role: Role) -> Option<(&'static str, &'static str)> {
+    match role {
+        Role::Admin => Some(("ADMIN", "bg-red-700 text-white")),
+        Role::Mod => Some(("MOD", "bg-purple-800 text-white")),
+        Role::Member => None,
+    }
+}
+
+fn render_role_badge(role: Option<Role>) -> Html {
+    match role.and_then(role_badge) {
+        Some((label, colors)) => html! {
+            <span class={classes!("ml-1", "px-1", "rounded", "text-[10px]", "font-bold", colors)}>{label}</span>
+        },
+        None => html! {},
     }
+}
 
-    fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
-        match msg {
-            Msg::HandleMsg(s) => {
-                let msg: WebSocketMessage = serde_json::from_str(&s).unwrap();
-                match msg.message_type {
-                    MsgTypes::Users => {
-                        let users_from_message = msg.data_array.unwrap_or_default();
-                        self.users = users_from_message
-                            .iter()
-                            .map(|u| UserProfile {
-                                name: u.into(),
-                                avatar: format!(
-                                    "https://avatars.dicebear.com/api/adventurer-neutral/{}.svg",
-                                    u
-                                )
-                                .into(),
-                            })
-                            .collect();
-                        return true;
-                    }
-                    MsgTypes::Message => {
-                        let message_data: MessageData =
-                            serde_json::from_str(&msg.data.unwrap()).unwrap();
-                        self.messages.push(message_data);
-                        return true;
-                    }
-                    _ => {
-                        return false;
+fn render_reactions(link: &yew::html::Scope<Chat>, m: &MessageData, me: &str) -> Html {
+    let mut emojis: Vec<&Emoji> = m.reactions.keys().collect();
+    emojis.sort();
+    html! {
+        <div role="group" aria-label="Reactions" class="flex flex-wrap gap-1 mt-1">
+            {
+                emojis.into_iter().map(|emoji| {
+                    let users = &m.reactions[emoji];
+                    let mine = users.iter().any(|u| u == me);
+                    let label = format!("{} reacted with {}", users.join(", "), emoji);
+                    // Screen readers hear the emoji and count first, then who.
+                    let aria_label = format!(
+                        "{} {} {} from {}{}",
+                        emoji,
+                        users.len(),
+                        if users.len() == 1 { "reaction" } else { "reactions" },
+                        users.join(", "),
+                        if mine { ", press to remove yours" } else { "" }
+                    );
+                    let onclick = {
+                        let id = m.id.clone();
+                        let emoji = emoji.clone();
+                        link.callback(move |_| Msg::React(id.clone(), emoji.clone()))
+                    };
+                    html! {
+                        <div class="relative group">
+                            <button type="button" {onclick} aria-label={aria_label} aria-pressed={mine.to_string()} class={classes!("px-2", "py-0.5", "text-xs", "rounded-full", "bg-[var(--chat-input)]", "border", if mine { "border-[var(--chat-highlight)]" } else { "border-[var(--chat-border)]" })}>
+                                {format!("{} {}", emoji, users.len())}
+                            </button>
+                            <div aria-hidden="true" class="hidden group-hover:block group-focus-within:block absolute bottom-full left-0 mb-1 z-10 max-w-xs w-max px-2 py-1 text-xs text-[var(--chat-text)] bg-[var(--chat-panel)] border border-[var(--chat-border)] rounded shadow-lg break-words">
+                                {label}
+                            </div>
+                        </div>
                     }
+                }).collect::<Html>()
+            }
+            // Also revealed when focus is inside the bubble, so keyboard users can reach it.
+            <div role="group" aria-label="Add reaction" class="hidden group-hover/bubble:flex group-focus-within/bubble:flex gap-1">
+                {
+                    QUICK_REACTIONS.iter().enumerate().map(|(i, emoji)| {
+                        let onclick = {
+                            let id = m.id.clone();
+                            link.callback(move |_| Msg::React(id.clone(), emoji.to_string()))
+                        };
+                        html! {
+                            <button type="button" {onclick} aria-label={format!("React with {}", emoji)} title={format!("React with {} ({})", emoji, i + 1)} class="px-1 text-xs rounded bg-transparent border-none">{*emoji}</button>
+                        }
+                    }).collect::<Html>()
                 }
+            </div>
+        </div>
+    }
+}
+
+fn file_icon(mime_type: &str) -> &'static str {
+    match mime_type.split('/').next().unwrap_or_default() {
+        "audio" => "🎵",
+        "video" => "🎬",
+        "text" => "📝",
+        _ if mime_type == "application/pdf" => "📄",
+        _ if mime_type.contains("zip") || mime_type.contains("compressed") => "🗜",
+        _ => "📎",
+    }
+}
+
+// Name, size and type with a download link, for files that aren't shown inline.
+fn render_file_card(meta: &AttachmentMeta, href: &str) -> Html {
+    let kind = if meta.mime_type.is_empty() { "file" } else { meta.mime_type.as_str() };
+    html! {
+        <a href={href.to_string()} download={meta.name.clone()} title={format!("Download {}", meta.name)} class="flex items-center gap-3 mt-1 p-2 w-64 max-w-full rounded-lg border border-[var(--chat-border)] bg-[var(--chat-input)] no-underline">
+            <span class="text-2xl" aria-hidden="true">{file_icon(&meta.mime_type)}</span>
+            <span class="min-w-0">
+                <span class="block text-sm truncate text-[var(--chat-text)]">{&meta.name}</span>
+                <span class="block text-xs text-[var(--chat-muted)]">{format!("{} · {}", format_size(meta.size), kind)}</span>
+            </span>
+            <span class="ml-auto text-lg text-[var(--chat-highlight)]" aria-hidden="true">{"⇩"}</span>
+        </a>
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MediaKind {
+    Image,
+    Gif,
+    Video,
+}
+
+impl MediaKind {
+    fn of(message: &str, attachment: Option<&AttachmentMeta>) -> Option<Self> {
+        let mime = match attachment {
+            Some(meta) => meta.mime_type.as_str(),
+            None if message.starts_with("data:") => message[5..].split([';', ',']).next().unwrap_or_default(),
+            None => "",
+        };
+        let path = message.split(['?', '#']).next().unwrap_or_default().to_lowercase();
+        if mime == "image/gif" || path.ends_with(".gif") {
+            Some(MediaKind::Gif)
+        } else if mime.starts_with("image/") {
+            Some(MediaKind::Image)
+        } else if mime.starts_with("video/") || path.ends_with(".mp4") || path.ends_with(".webm") {
+            Some(MediaKind::Video)
+        } else {
+            None
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            MediaKind::Image => "image",
+            MediaKind::Gif => "GIF",
+            MediaKind::Video => "video",
+        }
+    }
+}
+
+// Folds a copy of a message we already have into the original, which keeps
+// its place; the copy's time and reactions win. False if it is new.
+// Frames that didn't decode: malformed ones are reported to the
+// integrator, incomplete ones are only logged.
+fn rejected_frame(origin: String, error: FrameError) -> Option<ChatEvent> {
+    match error {
+        FrameError::Malformed(reason) => {
+            log::error!("dropping malformed frame from {}: {}", origin, reason);
+            Some(ChatEvent::FrameRejected { server: origin, reason })
+        }
+        FrameError::Incomplete(what) => {
+            log::debug!("ignoring frame from {}: {}", origin, what);
+            None
+        }
+    }
+}
+
+// Who joined and who left since the last list from `origin`. The first list
+// from a server is everyone already there, not people joining.
+fn roster_changes(users: &[UserProfile], origin: &str, names: &[String], me: &str) -> (Vec<String>, Vec<String>) {
+    let known: Vec<&str> = users.iter().filter(|u| u.origin == origin).map(|u| u.name.as_str()).collect();
+    if known.is_empty() {
+        return (vec![], vec![]);
+    }
+    let joined = names
+        .iter()
+        .filter(|name| !known.contains(&name.as_str()) && *name != me)
+        .cloned()
+        .collect();
+    let left = known
+        .into_iter()
+        .filter(|name| !names.iter().any(|n| n == name) && *name != me)
+        .map(String::from)
+        .collect();
+    (joined, left)
+}
+
+// The new list of `origin`'s users, keeping what we already knew about each.
+fn roster(
+    users: &[UserProfile],
+    origin: &str,
+    names: &[String],
+    details: &[UserDetails],
+    now: f64,
+    config: &ChatConfig,
+) -> Vec<UserProfile> {
+    names
+        .iter()
+        .map(|name| {
+            let detail = details.iter().find(|d| &d.nick == name);
+            let joined_at = detail.and_then(|d| d.joined_at);
+            let role = detail.and_then(|d| d.role);
+            let online = detail.and_then(|d| d.online).unwrap_or(true);
+            let last_seen = detail.and_then(|d| d.last_seen);
+            match users.iter().find(|u| u.origin == origin && u.name == *name) {
+                Some(existing) => UserProfile {
+                    joined_at,
+                    role,
+                    online,
+                    last_seen: last_seen.or(if existing.online && !online {
+                        Some(now)
+                    } else {
+                        existing.last_seen
+                    }),
+                    ..existing.clone()
+                },
+                None => UserProfile {
+                    origin: origin.into(),
+                    joined_at,
+                    role,
+                    online,
+                    last_seen,
+                    ..UserProfile::new(name, config)
+                },
             }
-            Msg::SubmitMessage => {
-                let input = self.chat_input.cast::<HtmlInputElement>();
-                if let Some(input) = input {
-                    let message = WebSocketMessage {
-                        message_type: MsgTypes::Message,
-                        data: Some(input.value()),
-                        data_array: None,
-                    };
-                    if let Err(e) = self
-                        .wss
-                        .tx
-                        .clone()
-                        .try_send(serde_json::to_string(&message).unwrap())
-                    {
-                        log::debug!("error sending to channel: {:?}", e);
-                    }
-                    input.set_value("");
-                };
-                false
+        })
+        .collect()
+}
+
+// Folds a repeat into the last bubble; hands the message back when it needs
+// a bubble of its own.
+fn fold_repeat(messages: &mut [MessageData], message: MessageData, collapse_repeats: bool) -> Option<MessageData> {
+    match messages.last_mut() {
+        // Thread replies go to the side panel, so they never fold into
+        // whatever the main timeline shows last.
+        _ if message.thread_id.is_some() => Some(message),
+        Some(last) if collapse_repeats && message.is_repeat_of(last) => {
+            last.repeats += 1;
+            None
+        }
+        _ => Some(message),
+    }
+}
+
+// Returns whether the reactions changed, and whether it was someone reacting
+// to one of `me`'s messages.
+fn apply_reaction(
+    messages: &mut [MessageData],
+    message_id: &str,
+    emoji: &Emoji,
+    from: &str,
+    remove: bool,
+    me: &str,
+) -> (bool, bool) {
+    match messages.iter_mut().find(|m| m.id == message_id) {
+        Some(m) if remove => {
+            let users = m.reactions.entry(emoji.clone()).or_default();
+            let before = users.len();
+            users.retain(|u| u != from);
+            let changed = users.len() != before;
+            if users.is_empty() {
+                m.reactions.remove(emoji);
+            }
+            (changed, false)
+        }
+        Some(m) => {
+            let users = m.reactions.entry(emoji.clone()).or_default();
+            if users.iter().any(|u| u == from) {
+                (false, false)
+            } else {
+                users.push(from.into());
+                (true, m.from == me)
             }
         }
+        None => (false, false),
     }
+}
 
-    fn view(&self, ctx: &Context<Self>) -> Html {
-        let submit = ctx.link().callback(|_| Msg::SubmitMessage);
+// Only the author can edit, so an edit naming someone else is ignored.
+fn apply_edit(
+    messages: &mut [MessageData],
+    search_index: &mut SearchIndex,
+    message_id: &str,
+    message: String,
+    from: &str,
+) -> bool {
+    let index = match messages.iter().position(|m| m.id == message_id && m.from == from) {
+        Some(index) => index,
+        None => return false,
+    };
+    let old = &messages[index].message;
+    if !old.starts_with("data:") {
+        search_index.remove_message(index, old);
+    }
+    if !message.starts_with("data:") {
+        search_index.index_message(index, &message);
+    }
+    messages[index].message = message;
+    messages[index].edited = true;
+    true
+}
 
-        html! {
-            <div class="flex w-screen gothic">
-                <div class="flex-none w-56 h-screen bg-black border-r border-red-900">
-                    <div class="text-xl p-3 border-b border-red-900">{"☠ Users"}</div>
-                    {
-                        self.users.iter().map(|u| {
-                            html!{
-                                <div class="flex m-3 bg-[#1f1f1f] rounded-lg p-2 border border-red-900 shadow-inner">
-                                    <img class="w-12 h-12 rounded-full avatar-frame" src={u.avatar.clone()} alt="avatar"/>
-                                    <div class="flex-grow p-3 text-sm">
-                                        <div>{&u.name}</div>
-                                        <div class="text-xs text-gray-400">{"Summoned..."}</div>
-                                    </div>
-                                </div>
-                            }
-                        }).collect::<Html>()
-                    }
-                </div>
-                <div class="grow h-screen flex flex-col bg-[#121212]">
-                    <div class="w-full h-14 border-b-2 border-red-900">
-                        <div class="text-xl p-3">{"💬 SpellCast Chat"}</div>
-                    </div>
-                    <div class="grow overflow-auto p-4 space-y-3">
-                        {
-                            self.messages.iter().map(|m| {
-                                let user = self.users.iter().find(|u| u.name == m.from).unwrap();
-                                html!{
-                                    <div class="flex bg-[#1e1e1e] p-3 rounded-lg border border-red-800 shadow-sm w-fit max-w-[70%]">
-                                        <img class="w-8 h-8 rounded-full avatar-frame mr-3" src={user.avatar.clone()} />
-                                        <div>
-                                            <div class="text-sm font-semibold text-red-500">{m.from.clone()}</div>
-                                            {
-                                                if m.message.ends_with(".gif") {
-                                                    html! { <img class="mt-2 rounded" src={m.message.clone()} /> }
-                                                } else {
-                                                    html! { <div class="text-sm text-gray-300">{m.message.clone()}</div> }
-                                                }
-                                            }
-                                        </div>
-                                    </div>
-                                }
-                            }).collect::<Html>()
-                        }
-                    </div>
-                    <div class="flex items-center px-3 py-4 border-t border-red-800 bg-black">
-                        <input ref={self.chat_input.clone()} type="text" placeholder="Speak your mind..." class="w-full py-2 px-4 bg-[#1a1a1a] rounded-full text-white outline-none" />
-                        <button onclick={submit} class="ml-3 bg-red-800 hover:bg-red-700 text-white p-3 rounded-full">
-                            <svg viewBox="0 0 24 24" class="w-5 h-5 fill-white"><path d="M2.01 21L23 12 2.01 3 2 10l15 2-15 2z"/></svg>
-                        </button>
-                    </div>
-                </div>
-            </div>
+fn merge_copy(messages: &mut [MessageData], message: &mut MessageData) -> bool {
+    match messages.iter_mut().find(|m| m.id == message.id) {
+        Some(existing) => {
+            existing.time = message.time.or(existing.time);
+            existing.merge_reactions(std::mem::take(&mut message.reactions));
+            true
+        }
+        None => false,
+    }
+}
+
+// Backfills overlap what we have. Known ids merge into `messages`; what is
+// left comes back to be added, each id once, along with the known ids.
+fn merge_backfill(messages: &mut [MessageData], batch: Vec<MessageData>) -> (Vec<MessageData>, Vec<MessageId>) {
+    let mut fresh: Vec<MessageData> = vec![];
+    let mut known = vec![];
+    for mut message in batch {
+        if merge_copy(messages, &mut message) {
+            known.push(message.id);
+        } else if !merge_copy(&mut fresh, &mut message) {
+            fresh.push(message);
         }
+    }
+    (fresh, known)
+}
+
+// What a bubble's body shows, decided once per message so new kinds get a
+// single place to slot in.
+#[derive(Debug, PartialEq)]
+enum MessageContent<'a> {
+    // Joins, leaves and notices: always shown as typed.
+    System,
+    // Plain text mode: the text as typed, nothing embedded.
+    Plain,
+    // Media held back until clicked.
+    MediaLink(MediaKind),
+    Video,
+    File(&'a AttachmentMeta),
+    // Text, images and GIFs, through the renderer chain.
+    Rich,
+}
+
+// `loaded` is whether the user already clicked through a media link.
+fn message_content(m: &MessageData, plain_text: bool, media_links: bool, loaded: bool) -> MessageContent<'_> {
+    let media = MediaKind::of(&m.message, m.attachment.as_ref());
+    match (&m.attachment, media) {
+        _ if m.kind == MessageKind::System => MessageContent::System,
+        _ if plain_text => MessageContent::Plain,
+        (_, Some(kind)) if media_links && !loaded => MessageContent::MediaLink(kind),
+        (_, Some(MediaKind::Video)) => MessageContent::Video,
+        (Some(meta), _) if !meta.mime_type.starts_with("image/") => MessageContent::File(meta),
+        _ => MessageContent::Rich,
+    }
+}
+
+// Stands in for media until clicked, so nothing is downloaded up front.
+fn render_media_link(link: &yew::html::Scope<Chat>, m: &MessageData, kind: MediaKind) -> Html {
+    let onclick = {
+        let id = m.id.clone();
+        link.callback(move |_| Msg::LoadMedia(id.clone()))
+    };
+    let detail = match &m.attachment {
+        Some(meta) => format!(" · {} · {}", meta.name, format_size(meta.size)),
+        None if m.message.starts_with("data:") => String::new(),
+        None => format!(" · {}", m.message),
+    };
+    html! {
+        <button type="button" {onclick} class="flex items-center gap-2 mt-1 p-2 max-w-full rounded-lg border border-[var(--chat-border)] bg-[var(--chat-input)] text-left text-sm text-[var(--chat-highlight)]">
+            <span aria-hidden="true">{if kind == MediaKind::Video { "▶" } else { "🖼" }}</span>
+            <span class="truncate">{format!("Load {}{}", kind.label(), detail)}</span>
+        </button>
+    }
+}
 
+fn snippet(message: &str) -> String {
+    const MAX_CHARS: usize = 80;
+    if message.starts_with("data:") {
+        return "📎 Attachment".into();
+    }
+    let mut snippet: String = message.chars().take(MAX_CHARS).collect();
+    if message.chars().count() > MAX_CHARS {
+        snippet.push('…');
     }
-}
\ No newline at end of file
+    snippet
+}
+
+fn mentions(message: &str, name: &str) -> bool {
+    message
+        .to_lowercase()
+        .contains(&format!("@{}", name.to_lowercase()))
+}
+
+// Every character of the query appears in order, e.g. "gnl" matches "general".
+fn fuzzy_match(query: &str, candidate: &str) -> bool {
+    let mut candidate = candidate.chars().flat_map(char::to_lowercase);
+    query
+        .chars()
+        .flat_map(char::to_lowercase)
+        .filter(|c| !c.is_whitespace())
+        .all(|q| candidate.any(|c| c == q))
+}
+
+fn render_user_skeleton() -> Html {
+    html! {
+        <div class="flex items-center m-3 p-2 gap-3 rounded-lg border border-[var(--chat-border)] bg-[var(--chat-surface)] animate-pulse">
+            <div class="w-12 h-12 rounded-full bg-[var(--chat-input)]"></div>
+            <div class="grow space-y-2">
+                <div class="h-3 w-2/3 rounded bg-[var(--chat-input)]"></div>
+                <div class="h-2 w-1/3 rounded bg-[var(--chat-input)]"></div>
+            </div>
+        </div>
+    }
+}
+
+fn render_message_skeleton(width: &'static str) -> Html {
+    html! {
+        <div class="flex items-start gap-3 w-fit p-3 rounded-lg border border-[var(--chat-border)] bg-[var(--chat-surface)] animate-pulse">
+            <div class="w-8 h-8 rounded-full bg-[var(--chat-input)]"></div>
+            <div class="space-y-2">
+                <div class="h-3 w-20 rounded bg-[var(--chat-input)]"></div>
+                <div class={classes!("h-3", "rounded", "bg-[var(--chat-input)]", width)}></div>
+            </div>
+        </div>
+    }
+}
+
+#[derive(Deserialize)]
+struct PermalinkQuery {
+    msg: Option<MessageId>,
+}
+
+// The message a `/chat/:room?msg=<id>` link points at.
+fn permalink(ctx: &Context<Chat>) -> Option<MessageId> {
+    ctx.link()
+        .location()?
+        .query::<PermalinkQuery>()
+        .ok()?
+        .msg
+}
+
+// `ClipboardEvent` is still behind web-sys' unstable flag, so the clipboard
+// data is read through Reflect.
+fn pasted_images(e: &Event) -> Vec<File> {
+    let files = js_sys::Reflect::get(e, &JsValue::from_str("clipboardData"))
+        .ok()
+        .and_then(|data| data.dyn_into::<DataTransfer>().ok())
+        .and_then(|data| data.files());
+    let files = match files {
+        Some(files) => files,
+        None => return vec![],
+    };
+    let now = js_sys::Date::now() as u64;
+    (0..files.length())
+        .filter_map(|i| files.get(i))
+        .filter(|f| f.type_().starts_with("image/"))
+        .enumerate()
+        .map(|(n, f)| {
+            // Browsers call every pasted image "image.png"; rename so they
+            // don't overwrite each other while being read.
+            let mime_type = f.type_();
+            let extension = mime_type.trim_start_matches("image/").to_string();
+            let name = format!("pasted-{}-{}.{}", now, n, extension);
+            File::new_with_options(&name, Blob::from(web_sys::Blob::from(f)), Some(&mime_type), None)
+        })
+        .collect()
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1_048_575 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+// Why a file can't be attached, checked before it is read.
+fn attachment_rejection(config: &ChatConfig, mime_type: &str, size: u64) -> Option<String> {
+    let allowed = config.attachment_types.iter().any(|allowed| match allowed.strip_suffix("/*") {
+        Some(family) => mime_type.split('/').next() == Some(family),
+        None => allowed == mime_type,
+    });
+    if !allowed {
+        Some("Unsupported type".to_string())
+    } else if size > config.max_attachment_bytes {
+        Some(format!("File too large (max {})", format_size(config.max_attachment_bytes)))
+    } else {
+        None
+    }
+}
+
+// Matches the composer's `leading-6` and `py-2`.
+const COMPOSER_LINE_PX: i32 = 24;
+const COMPOSER_PADDING_PX: i32 = 16;
+
+// Sizes the composer to its content, capped at `max_lines`; past that it
+// scrolls inside instead of pushing the message list away.
+fn autogrow(input: &HtmlTextAreaElement, max_lines: u32) {
+    let style = input.style();
+    let _ = style.set_property("height", "auto");
+    let max_px = max_lines as i32 * COMPOSER_LINE_PX + COMPOSER_PADDING_PX;
+    let content_px = input.scroll_height();
+    let _ = style.set_property("height", &format!("{}px", content_px.min(max_px)));
+    let _ = style.set_property("overflow-y", if content_px > max_px { "auto" } else { "hidden" });
+}
+
+const COMPOSER_HISTORY_LIMIT: usize = 100;
+// Keystrokes closer together than this undo as one step.
+const COMPOSER_COALESCE_MS: f64 = 1000.0;
+
+// Our own undo stack for the composer. Setting the value from code (mention
+// completion, drafts) wipes the browser's native one.
+#[derive(Default)]
+struct ComposerHistory {
+    past: Vec<String>,
+    future: Vec<String>,
+    current: String,
+    last_edit: f64,
+}
+
+impl ComposerHistory {
+    fn reset(&mut self, text: &str) {
+        *self = Self {
+            current: text.to_string(),
+            ..Self::default()
+        };
+    }
+
+    // `typed` edits coalesce with the one before; anything else is its own step.
+    fn record(&mut self, text: &str, typed: bool) {
+        if text == self.current {
+            return;
+        }
+        let now = js_sys::Date::now();
+        if !typed || now - self.last_edit > COMPOSER_COALESCE_MS {
+            self.past.push(std::mem::take(&mut self.current));
+            if self.past.len() > COMPOSER_HISTORY_LIMIT {
+                self.past.remove(0);
+            }
+        }
+        self.future.clear();
+        self.current = text.to_string();
+        self.last_edit = if typed { now } else { 0.0 };
+    }
+
+    fn undo(&mut self) -> Option<&str> {
+        let previous = self.past.pop()?;
+        self.future.push(std::mem::replace(&mut self.current, previous));
+        self.last_edit = 0.0;
+        Some(&self.current)
+    }
+
+    fn redo(&mut self) -> Option<&str> {
+        let next = self.future.pop()?;
+        self.past.push(std::mem::replace(&mut self.current, next));
+        self.last_edit = 0.0;
+        Some(&self.current)
+    }
+}
+
+fn activity_ttl_ms(config: &ChatConfig, kind: ActivityKind) -> u32 {
+    match kind {
+        ActivityKind::Typing => config.typing_ttl_ms,
+        _ => config.activity_ttl_ms,
+    }
+}
+
+// Browsers throttle timers in background tabs, so an expiry can land late;
+// the view checks the clock too instead of trusting the timer alone.
+fn still_active(config: &ChatConfig, kind: ActivityKind, since: f64, now: f64) -> bool {
+    now - since < f64::from(activity_ttl_ms(config, kind))
+}
+
+fn format_ago(elapsed_ms: f64) -> String {
+    let minutes = (elapsed_ms / 60_000.0).max(0.0) as u64;
+    match minutes {
+        0 => "just now".to_string(),
+        1..=59 => format!("{}m ago", minutes),
+        60..=1439 => format!("{}h ago", minutes / 60),
+        _ => format!("{}d ago", minutes / 1440),
+    }
+}
+
+fn latency_class(latency_ms: u32) -> &'static str {
+    match latency_ms {
+        0..=149 => "text-green-500",
+        150..=399 => "text-amber-500",
+        _ => "text-red-500",
+    }
+}
+
+// Intl options for `toLocaleString`; leaving out the date fields formats
+// only the time.
+fn time_options(clock: ClockFormat, with_date: bool) -> JsValue {
+    let options = js_sys::Object::new();
+    let set = |key: &str, value: JsValue| {
+        let _ = js_sys::Reflect::set(&options, &JsValue::from_str(key), &value);
+    };
+    if with_date {
+        set("dateStyle", "medium".into());
+        set("timeStyle", "medium".into());
+    } else {
+        set("hour", "numeric".into());
+        set("minute", "2-digit".into());
+    }
+    if let Some(hour12) = clock.hour12() {
+        set("hour12", hour12.into());
+    }
+    options.into()
+}
+
+// "1:05 PM" or "13:05", for when the user picked a clock over the locale's.
+fn clock_time(hours: u32, minutes: u32, hour12: bool) -> String {
+    if hour12 {
+        let suffix = if hours < 12 { "AM" } else { "PM" };
+        let hours = match hours % 12 {
+            0 => 12,
+            h => h,
+        };
+        format!("{}:{:02} {}", hours, minutes, suffix)
+    } else {
+        format!("{:02}:{:02}", hours, minutes)
+    }
+}
+
+fn format_time(millis: f64, clock: ClockFormat) -> String {
+    let date = js_sys::Date::new(&JsValue::from_f64(millis));
+    match clock.hour12() {
+        Some(hour12) => clock_time(date.get_hours(), date.get_minutes(), hour12),
+        None => String::from(date.to_locale_string("default", &time_options(clock, false))),
+    }
+}
+
+fn format_datetime(millis: f64, clock: ClockFormat) -> String {
+    let date = js_sys::Date::new(&JsValue::from_f64(millis));
+    String::from(date.to_locale_string("default", &time_options(clock, true)))
+}
+
+pub struct Chat {
+    user: User,
+    users: Vec<UserProfile>,
+    // Everyone we have messages from, online or not.
+    profiles: HashMap<String, UserProfile>,
+    config: ChatConfig,
+    chat_input: NodeRef,
+    _producer: Box<dyn Bridge<EventBus>>,
+    wss: Vec<WebsocketService>,
+    messages: Vec<MessageData>,
+    latency_ms: Option<u32>,
+    pending_attachments: Vec<Attachment>,
+    attachment_error: Option<String>,
+    // Keyed per upload, since pasted images often share a file name.
+    readers: HashMap<u32, FileReader>,
+    next_reader_id: u32,
+    renaming: bool,
+    rename_input: NodeRef,
+    rename_error: Option<String>,
+    blocked: HashSet<String>,
+    replying_to: Option<MessageId>,
+    reply_quote: Option<String>,
+    open_thread: Option<MessageId>,
+    thread_input: NodeRef,
+    editing: Option<MessageId>,
+    edit_input: NodeRef,
+    // Messages someone else is editing right now.
+    remote_editing: HashMap<MessageId, Timeout>,
+    // Unsent composer text per room, kept across room switches and reloads.
+    drafts: HashMap<String, String>,
+    composer_history: ComposerHistory,
+    show_preview: bool,
+    mention: Option<MentionToken>,
+    mention_selected: usize,
+    message_refs: HashMap<MessageId, NodeRef>,
+    highlighted: Option<MessageId>,
+    // Target for quick-reaction shortcuts; falls back to the latest message.
+    hovered: Option<MessageId>,
+    // Messages that arrived while the tab was hidden, marked until the user
+    // does something after coming back.
+    away_ids: HashSet<MessageId>,
+    delivery: HashMap<MessageId, Delivery>,
+    // How many people each of our messages went out to.
+    recipients: HashMap<MessageId, usize>,
+    // Who has read up to each message, and when.
+    receipts: HashMap<MessageId, Vec<(String, f64)>>,
+    last_read_sent: Option<MessageId>,
+    // What other people in the current room are doing right now.
+    // Kind, when it was last announced, and the timer that clears it.
+    activity: HashMap<String, (ActivityKind, f64, Timeout)>,
+    last_activity_sent: f64,
+    ack_timeouts: HashMap<MessageId, Timeout>,
+    expanded: HashSet<MessageId>,
+    // Media the user clicked to load while `media_links` is on.
+    loaded_media: HashSet<MessageId>,
+    expanded_context: HashSet<MessageId>,
+    _highlight_timeout: Option<Timeout>,
+    settings: SettingsContext,
+    _settings_handle: ContextHandle<SettingsContext>,
+    show_settings: bool,
+    current_room: String,
+    rooms: Vec<String>,
+    new_room_input: NodeRef,
+    search_index: SearchIndex,
+    search_query: String,
+    connections: Vec<ConnectionState>,
+    ever_open: Vec<bool>,
+    // When each reconnecting connection will try again, for the countdown.
+    reconnect_at: Vec<Option<f64>>,
+    _countdown: Option<Interval>,
+    show_traffic: bool,
+    _traffic_refresh: Option<Interval>,
+    // Message indices where a connection came back after a drop.
+    gaps: Vec<usize>,
+    renderers: Vec<Box<dyn MessageRenderer>>,
+    // Notification sounds by url, loaded up front so the first one isn't late.
+    sounds: HashMap<String, HtmlAudioElement>,
+    toasts: Vec<Toast>,
+    // Server-wide banners as (id, text), minus the ones dismissed before.
+    announcements: Vec<(String, String)>,
+    dismissed_announcements: HashSet<String>,
+    pending_reactions: Vec<(String, Emoji)>,
+    _reaction_digest: Option<Timeout>,
+    next_toast_id: u32,
+    user_filter: String,
+    sidebar_user_limit: usize,
+    // Per-user message counts since this page was opened.
+    show_stats: bool,
+    stats_sort: StatsSort,
+    session_started: f64,
+    gif_picker_open: bool,
+    emoji_picker_open: bool,
+    emoji_selected: usize,
+    emoji_picker: NodeRef,
+    // Moves focus into the picker when it opens and back out when it closes.
+    emoji_picker_focus: bool,
+    gif_input: NodeRef,
+    gif_query: String,
+    gif_results: Vec<Gif>,
+    gif_loading: bool,
+    gif_error: Option<String>,
+    // Index of the first message that arrived while the tab was hidden.
+    unread_marker: Option<usize>,
+    // The newest message read in each room, kept across sessions.
+    last_read: HashMap<String, MessageId>,
+    // Scrolled into view after the next render.
+    scroll_to: Option<MessageId>,
+    pending_permalink: Option<MessageId>,
+    message_menu: Option<(MessageId, i32, i32)>,
+    message_list: NodeRef,
+    saved_scroll: Option<i32>,
+    // Sits above the oldest message; scrolling it into view loads the page before.
+    older_sentinel: NodeRef,
+    older_observer: Option<(IntersectionObserver, ObserverCallback)>,
+    observed_sentinel: Option<Element>,
+    loading_older: bool,
+    // Rooms whose server has nothing older left.
+    history_exhausted: HashSet<String>,
+    // Distance from the bottom of the list, kept while a page is prepended
+    // so the view doesn't jump.
+    older_anchor: Option<i32>,
+    restore_view: bool,
+    _visibility_listener: EventListener,
+    online: bool,
+    _online_listeners: [EventListener; 2],
+    // Placeholders are shown until the first user list arrives.
+    loaded_users: bool,
+    pending_users: HashMap<String, (Vec<String>, Vec<UserDetails>)>,
+    _users_debounce: Option<Timeout>,
+    frame_commands: Vec<FrameCommand>,
+    room_switcher_open: bool,
+    // Hides the sidebar and header, leaving the messages and composer.
+    focus_mode: bool,
+    room_switcher_query: String,
+    room_switcher_input: NodeRef,
+    _keydown_listener: EventListener,
+    _unload_listener: EventListener,
+}
+impl Chat {
+    fn connect(ctx: &Context<Self>, config: &ChatConfig, index: usize) -> WebsocketService {
+        let mut wss = WebsocketService::new(
+            &ctx.props().servers[index],
+            ctx.link().callback(move |state| Msg::ConnectionChanged(index, state)),
+            ctx.props().reconnect_jitter,
+            if config.debug_traffic { config.traffic_log_limit } else { 0 },
+        );
+        wss.start_heartbeat(config.heartbeat_interval_ms, || {
+            let ping = WebSocketMessage {
+                message_type: MsgTypes::Ping,
+                data: Some(js_sys::Date::now().to_string()),
+                ..Default::default()
+            };
+            serde_json::to_string(&ping).unwrap()
+        });
+        wss
+    }
+
+    // Runs on every successful (re)connect, since the server forgets us when
+    // the socket drops.
+    fn register(&self, index: usize) {
+        let username = self.user.username.borrow().clone();
+        let greeting = [
+            WebSocketMessage {
+                message_type: MsgTypes::Register,
+                data: Some(username),
+                ..Default::default()
+            },
+            WebSocketMessage {
+                message_type: MsgTypes::Join,
+                data: Some(self.current_room.clone()),
+                ..Default::default()
+            },
+        ];
+        if let Err(e) = self.wss[index].greet(&greeting) {
+            log::debug!("error registering with {}: {}", self.wss[index].url, e);
+        }
+    }
+
+    fn theme(&self, ctx: &Context<Self>) -> Theme {
+        match self.settings.theme {
+            ThemeMode::Dark => ctx.props().theme.clone(),
+            ThemeMode::Light => Theme::light(),
+        }
+    }
+
+    fn alert(&self, message: &MessageData) {
+        let me = self.user.username.borrow().clone();
+        if message.from == me || self.blocked.contains(&message.from) {
+            return;
+        }
+        let mentioned = mentions(&message.message, &me)
+            || (self.settings.notify_everyone && mentions_everyone(&message.message));
+        match self.settings.notify_level(message.room()) {
+            NotifyLevel::All => {}
+            NotifyLevel::Mentions if mentioned => {}
+            _ => return,
+        }
+        if self.settings.sound {
+            let choice = if mentioned {
+                &self.settings.mention_sound
+            } else {
+                &self.settings.message_sound
+            };
+            let audio = self
+                .config
+                .sound_url(&choice.sound)
+                .and_then(|url| self.sounds.get(url));
+            if let Some(audio) = audio {
+                audio.set_volume(f64::from(choice.volume.min(100)) / 100.0);
+                audio.set_current_time(0.0);
+                let _ = audio.play();
+            }
+        }
+        self.desktop_notify(&message.from, &snippet(&message.message));
+    }
+
+    fn desktop_notify(&self, title: &str, body: &str) {
+        if self.settings.notifications
+            && gloo::utils::document().hidden()
+            && platform::notifications_granted()
+        {
+            let mut options = NotificationOptions::new();
+            options.body(body);
+            let _ = Notification::new_with_options(title, &options);
+        }
+    }
+
+    fn flush_reactions(&mut self, ctx: &Context<Self>) {
+        self._reaction_digest = None;
+        let reactions = std::mem::take(&mut self.pending_reactions);
+        let text = match reactions.as_slice() {
+            [] => return,
+            [(from, emoji)] => format!("{} reacted {} to your message", from, emoji),
+            _ => {
+                let mut people: Vec<&str> = vec![];
+                let mut emojis: Vec<&str> = vec![];
+                for (from, emoji) in &reactions {
+                    if !people.contains(&from.as_str()) {
+                        people.push(from);
+                    }
+                    if !emojis.contains(&emoji.as_str()) {
+                        emojis.push(emoji);
+                    }
+                }
+                let people = match people.as_slice() {
+                    [first, second, _, rest @ ..] if !rest.is_empty() => {
+                        format!("{}, {} and {} others", first, second, rest.len() + 1)
+                    }
+                    [init @ .., last] if !init.is_empty() => {
+                        format!("{} and {}", init.join(", "), last)
+                    }
+                    _ => people.concat(),
+                };
+                format!("{} reacted {} to your messages", people, emojis.concat())
+            }
+        };
+        self.desktop_notify("New reactions", &text);
+        self.toast(ctx, text);
+    }
+
+    fn toast(&mut self, ctx: &Context<Self>, text: String) {
+        self.push_toast(ctx, text, false);
+    }
+
+    fn push_toast(&mut self, ctx: &Context<Self>, text: String, success: bool) {
+        let id = self.next_toast_id;
+        self.next_toast_id += 1;
+        let link = ctx.link().clone();
+        self.toasts.push(Toast {
+            id,
+            text,
+            success,
+            _timeout: Timeout::new(self.config.toast_duration_ms, move || {
+                link.send_message(Msg::DismissToast(id))
+            }),
+        });
+    }
+
+    fn push_message(&mut self, message: MessageData) {
+        if !self.profiles.contains_key(&message.from) {
+            self.profiles
+                .insert(message.from.clone(), UserProfile::new(&message.from, &self.config));
+        }
+        self.message_refs
+            .insert(message.id.clone(), NodeRef::default());
+        if !message.message.starts_with("data:") {
+            self.search_index
+                .index_message(self.messages.len(), &message.message);
+        }
+        self.messages.push(message);
+    }
+
+    fn push_system(&mut self, room: Option<String>, text: String, detail: Option<String>, now: f64) {
+        let id = format!("system-{}-{}", now, self.messages.len());
+        self.message_refs.insert(id.clone(), NodeRef::default());
+        self.messages.push(MessageData {
+            id,
+            from: String::new(),
+            message: text,
+            time: Some(now),
+            reactions: HashMap::new(),
+            repeats: 0,
+            reply_to: None,
+            quote: None,
+            thread_id: None,
+            room,
+            origin: None,
+            edited: false,
+            attachment: None,
+            kind: MessageKind::System,
+            detail,
+        });
+    }
+
+    // A page from before everything we have, so it goes in at the front and
+    // every index into `messages` moves along with it.
+    // `page_len` counts every entry the server sent, readable or not.
+    fn prepend_history(&mut self, room: Option<String>, page_len: usize, batch: Vec<MessageData>) -> bool {
+        self.loading_older = false;
+        let room = room.unwrap_or_else(|| self.current_room.clone());
+        if page_len < self.config.history_page_size {
+            self.history_exhausted.insert(room);
+        }
+        let (mut older, known) = merge_backfill(&mut self.messages, batch);
+        for id in &known {
+            self.mark_sent(id);
+        }
+        // Re-observing fires again if the sentinel is still on screen.
+        self.observed_sentinel = None;
+        if older.is_empty() {
+            return true;
+        }
+        older.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(std::cmp::Ordering::Equal));
+        self.older_anchor = self
+            .message_list
+            .cast::<Element>()
+            .map(|list| list.scroll_height() - list.scroll_top());
+        let shift = older.len();
+        for message in &older {
+            if !self.profiles.contains_key(&message.from) {
+                self.profiles
+                    .insert(message.from.clone(), UserProfile::new(&message.from, &self.config));
+            }
+            self.message_refs.insert(message.id.clone(), NodeRef::default());
+        }
+        self.messages.splice(0..0, older);
+        for gap in &mut self.gaps {
+            *gap += shift;
+        }
+        if let Some(marker) = &mut self.unread_marker {
+            *marker += shift;
+        }
+        self.search_index = SearchIndex::default();
+        for (index, message) in self.messages.iter().enumerate() {
+            if message.kind == MessageKind::User && !message.message.starts_with("data:") {
+                self.search_index.index_message(index, &message.message);
+            }
+        }
+        true
+    }
+
+    // Our own message coming back, or one we already have being replayed by
+    // a backfill: keep the bubble and take the server's reaction state.
+    // Returns false for messages we haven't seen.
+    fn merge_known(&mut self, message: &mut MessageData) -> bool {
+        if !merge_copy(&mut self.messages, message) {
+            return false;
+        }
+        self.mark_sent(&message.id);
+        true
+    }
+
+    // Online users matching the `@` word being typed, best guesses first.
+    fn mention_candidates(&self) -> Vec<String> {
+        let query = match &self.mention {
+            Some(mention) => mention.query.to_lowercase(),
+            None => return vec![],
+        };
+        let me = self.user.username.borrow().clone();
+        let mut names: Vec<String> = vec![];
+        for user in &self.users {
+            if user.online && user.name != me && fuzzy_match(&query, &user.name) && !names.contains(&user.name) {
+                names.push(user.name.clone());
+            }
+        }
+        // Prefix matches beat scattered ones.
+        names.sort_by_key(|name| !name.to_lowercase().starts_with(&query));
+        names.truncate(5);
+        names
+    }
+
+    fn insert_mention(&mut self, name: &str) -> bool {
+        let (mention, input) = match (self.mention.take(), self.chat_input.cast::<HtmlTextAreaElement>()) {
+            (Some(mention), Some(input)) => (mention, input),
+            _ => return false,
+        };
+        let value = input.value();
+        if mention.end > value.len() {
+            return true;
+        }
+        let head = format!("{}@{} ", &value[..mention.start], name);
+        let caret = head.encode_utf16().count() as u32;
+        let value = format!("{}{}", head, &value[mention.end..]);
+        input.set_value(&value);
+        autogrow(&input, self.config.composer_max_lines);
+        let _ = input.set_selection_range(caret, caret);
+        let _ = input.focus();
+        self.composer_history.record(&value, false);
+        self.drafts.insert(self.current_room.clone(), value);
+        self.save_drafts();
+        true
+    }
+
+    // Replaces the composer's selection, or inserts at the caret.
+    fn insert_at_caret(&mut self, text: &str) {
+        let input = match self.chat_input.cast::<HtmlTextAreaElement>() {
+            Some(input) => input,
+            None => return,
+        };
+        let value = input.value();
+        // Selection offsets count UTF-16 units, not bytes.
+        let byte_at = |units: Option<u32>| {
+            let units = units.unwrap_or(u32::MAX) as usize;
+            let mut seen = 0;
+            value
+                .char_indices()
+                .find(|(_, c)| {
+                    seen += c.len_utf16();
+                    seen > units
+                })
+                .map_or(value.len(), |(i, _)| i)
+        };
+        let start = byte_at(input.selection_start().ok().flatten());
+        let end = byte_at(input.selection_end().ok().flatten()).max(start);
+        let head = format!("{}{}", &value[..start], text);
+        let caret = head.encode_utf16().count() as u32;
+        let value = format!("{}{}", head, &value[end..]);
+        input.set_value(&value);
+        autogrow(&input, self.config.composer_max_lines);
+        let _ = input.set_selection_range(caret, caret);
+        self.composer_history.record(&value, false);
+        self.drafts.insert(self.current_room.clone(), value);
+        self.save_drafts();
+    }
+
+    fn save_current_draft(&mut self) {
+        let text = match self.chat_input.cast::<HtmlTextAreaElement>() {
+            Some(input) => input.value(),
+            None => return,
+        };
+        if text.is_empty() {
+            self.drafts.remove(&self.current_room);
+        } else {
+            self.drafts.insert(self.current_room.clone(), text);
+        }
+        self.save_drafts();
+    }
+
+    fn save_drafts(&self) {
+        if let Err(e) = LocalStorage::set(DRAFTS_STORAGE_KEY, &self.drafts) {
+            log::error!("failed to persist drafts: {:?}", e);
+        }
+    }
+
+    // `body` is the message text, cut short if the bubble is collapsed.
+    fn render_content(&self, ctx: &Context<Self>, m: &MessageData, body: &str) -> Html {
+        let loaded = self.loaded_media.contains(&m.id);
+        match message_content(m, self.settings.plain_text, self.settings.media_links, loaded) {
+            MessageContent::System | MessageContent::Plain => render_plain(body),
+            MessageContent::MediaLink(kind) => render_media_link(ctx.link(), m, kind),
+            MessageContent::Video => html! {
+                <video class="mt-2 max-h-80 rounded" src={m.message.clone()} controls={true} preload="metadata" />
+            },
+            MessageContent::File(meta) => render_file_card(meta, &m.message),
+            MessageContent::Rich => render_message(&self.renderers, body),
+        }
+    }
+
+    fn restore_draft(&mut self) {
+        if let Some(input) = self.chat_input.cast::<HtmlTextAreaElement>() {
+            let draft = self.drafts.get(&self.current_room).map(String::as_str).unwrap_or_default();
+            input.set_value(draft);
+            autogrow(&input, self.config.composer_max_lines);
+            self.composer_history.reset(draft);
+        }
+    }
+
+    fn set_composer(&mut self, text: &str) {
+        if let Some(input) = self.chat_input.cast::<HtmlTextAreaElement>() {
+            input.set_value(text);
+            autogrow(&input, self.config.composer_max_lines);
+            let caret = text.encode_utf16().count() as u32;
+            let _ = input.set_selection_range(caret, caret);
+        }
+        self.mention = None;
+        if text.is_empty() {
+            self.drafts.remove(&self.current_room);
+        } else {
+            self.drafts.insert(self.current_room.clone(), text.to_string());
+        }
+        self.save_drafts();
+    }
+
+    fn save_history(&self) {
+        let kept: Vec<&MessageData> = self.messages.iter().filter(|m| m.kind == MessageKind::User).collect();
+        let start = kept.len().saturating_sub(self.config.history_limit);
+        if let Err(e) = LocalStorage::set(HISTORY_STORAGE_KEY, &kept[start..]) {
+            log::error!("failed to persist history: {:?}", e);
+        }
+    }
+
+    fn send_to(&self, index: usize, message: WebSocketMessage) {
+        if let Err(e) = self.try_send_to(index, &message) {
+            log::debug!("error sending to {}: {}", self.wss[index].url, e);
+        }
+    }
+
+    fn try_send_to(&self, index: usize, message: &WebSocketMessage) -> Result<(), WsError> {
+        self.wss[index].send_json(message)
+    }
+
+    // Posts to the primary server only, so relaying servers don't duplicate it.
+    fn send(&self, message: WebSocketMessage) {
+        self.send_to(0, message);
+    }
+
+    fn send_chat(
+        &mut self,
+        ctx: &Context<Self>,
+        text: String,
+        reply_to: Option<MessageId>,
+        quote: Option<String>,
+        thread_id: Option<MessageId>,
+        attachment: Option<AttachmentMeta>,
+    ) {
+        let room = Some(self.current_room.clone());
+        let id = if ctx.props().delivery_receipts {
+            let me = self.user.username.borrow().clone();
+            let now = js_sys::Date::now();
+            let id = format!("{}-{}-{}", me, now, (js_sys::Math::random() * 1e9) as u32);
+            // Shown right away and flipped to sent once the server acks.
+            self.push_message(MessageData {
+                id: id.clone(),
+                from: me,
+                message: text.clone(),
+                time: Some(now),
+                reactions: HashMap::new(),
+                repeats: 0,
+                reply_to: reply_to.clone(),
+                quote: quote.clone(),
+                thread_id: thread_id.clone(),
+                room: room.clone(),
+                origin: Some(self.wss[0].url.clone()),
+                edited: false,
+                attachment: attachment.clone(),
+                kind: MessageKind::User,
+                detail: None,
+            });
+            self.delivery.insert(id.clone(), Delivery::Sending);
+            self.recipients.insert(id.clone(), self.online_recipients());
+            let timeout = {
+                let link = ctx.link().clone();
+                let id = id.clone();
+                Timeout::new(self.config.ack_timeout_ms, move || link.send_message(Msg::AckTimeout(id)))
+            };
+            self.ack_timeouts.insert(id.clone(), timeout);
+            Some(id)
+        } else {
+            None
+        };
+        ctx.props().on_event.emit(ChatEvent::MessageSent {
+            room: self.current_room.clone(),
+        });
+        let message = WebSocketMessage {
+            message_type: MsgTypes::Message,
+            id: id.clone(),
+            data: Some(text),
+            reply_to,
+            quote,
+            thread_id,
+            room,
+            attachment,
+            ..Default::default()
+        };
+        if let Err(e) = self.try_send_to(0, &message) {
+            log::error!("could not send message: {}", e);
+            if e == WsError::BufferFull {
+                self.toast(ctx, "Too many messages are waiting to go out, try again shortly".into());
+            }
+            if let Some(id) = id {
+                self.ack_timeouts.remove(&id);
+                self.delivery.insert(id, Delivery::Failed);
+            }
+        }
+    }
+
+    // Applies one raw frame from `origin` to the chat state; returns whether
+    // the view needs to re-render. Malformed frames are logged and dropped.
+    // Anything that needs the component's link is queued on `frame_commands`
+    // for `run_frame_commands`, so this never touches `ctx`. `now` and
+    // `hidden` (whether the tab is in the background) come from the caller.
+    fn handle_frame(&mut self, origin: String, raw: &str, now: f64, hidden: bool) -> bool {
+        let incoming = match decode_frame(raw) {
+            Ok(incoming) => incoming,
+            Err(e) => {
+                if let Some(event) = rejected_frame(origin, e) {
+                    self.frame_commands.push(FrameCommand::Emit(event));
+                }
+                return false;
+            }
+        };
+        match incoming {
+            Incoming::Users { names, details } => {
+                // The first list replaces the placeholders right away.
+                if !self.loaded_users {
+                    self.loaded_users = true;
+                    return self.apply_users(origin, names, details, now);
+                }
+                // Busy servers can send several lists a second and only the
+                // latest one from each matters.
+                self.pending_users.insert(origin, (names, details));
+                if self._users_debounce.is_none() {
+                    self.frame_commands.push(FrameCommand::Arm(Timer::UsersDebounce));
+                }
+                false
+            }
+            Incoming::Unreadable { room, reason } => {
+                log::error!("dropping message frame from {}: {}", origin, reason);
+                self.frame_commands.push(FrameCommand::Emit(ChatEvent::FrameRejected {
+                    server: origin,
+                    reason: reason.clone(),
+                }));
+                if !self.settings.show_unreadable {
+                    return false;
+                }
+                self.push_system(room, "[unreadable message]".into(), Some(reason), now);
+                true
+            }
+            Incoming::Message(message_data) => {
+                let mut message_data = *message_data;
+                message_data.origin = Some(origin);
+                self.activity.remove(&message_data.from);
+                // Without receipts our own messages first show up here, as
+                // the server's echo.
+                if message_data.from == *self.user.username.borrow() && !self.recipients.contains_key(&message_data.id) {
+                    let count = self.online_recipients();
+                    self.recipients.insert(message_data.id.clone(), count);
+                }
+                if self.merge_known(&mut message_data) {
+                    self.save_history();
+                    return true;
+                }
+                self.frame_commands.push(FrameCommand::Emit(ChatEvent::MessageReceived {
+                    room: message_data.room().to_string(),
+                    from: message_data.from.clone(),
+                }));
+                self.alert(&message_data);
+                if hidden {
+                    self.away_ids.insert(message_data.id.clone());
+                }
+                if let Some(message_data) = fold_repeat(&mut self.messages, message_data, self.settings.collapse_repeats) {
+                    self.push_message(message_data);
+                }
+                self.save_history();
+                self.mark_read();
+                true
+            }
+            Incoming::Announcement { id, text } => {
+                if self.dismissed_announcements.contains(&id)
+                    || self.announcements.iter().any(|(known, _)| *known == id)
+                {
+                    return false;
+                }
+                self.announcements.push((id, text));
+                true
+            }
+            // A backfill answered in one batch rather than replayed frame by frame.
+            Incoming::History { before, room, entries } => {
+                let page_len = entries.len();
+                let entries: Vec<MessageData> = entries
+                    .into_iter()
+                    .filter_map(|entry| {
+                        entry
+                            .map_err(|e| log::error!("dropping malformed history entry from {}: {}", origin, e))
+                            .ok()
+                    })
+                    .map(|mut message_data| {
+                        message_data.origin = Some(origin.clone());
+                        message_data
+                    })
+                    .collect();
+                if before.is_some() {
+                    return self.prepend_history(room, page_len, entries);
+                }
+                let (fresh, known) = merge_backfill(&mut self.messages, entries);
+                for id in &known {
+                    self.mark_sent(id);
+                }
+                let changed = !fresh.is_empty() || !known.is_empty();
+                for message_data in fresh {
+                    self.push_message(message_data);
+                }
+                if changed {
+                    self.save_history();
+                }
+                changed
+            }
+            Incoming::Read { message_id, from, time } => {
+                if from == *self.user.username.borrow() {
+                    return false;
+                }
+                let receipts = self.receipts.entry(message_id).or_default();
+                if receipts.iter().any(|(user, _)| *user == from) {
+                    return false;
+                }
+                receipts.push((from, time.unwrap_or(now)));
+                true
+            }
+            Incoming::Rename(result) => {
+                if result.accepted {
+                    *self.user.username.borrow_mut() = result.name;
+                    self.renaming = false;
+                    self.rename_error = None;
+                } else {
+                    self.rename_error = Some(result.reason.unwrap_or_else(|| {
+                        format!("\"{}\" is not available", result.name)
+                    }));
+                }
+                true
+            }
+            Incoming::Reaction { message_id, emoji, from, remove } => {
+                let me = self.user.username.borrow().clone();
+                let (changed, mine) = apply_reaction(&mut self.messages, &message_id, &emoji, &from, remove, &me);
+                if mine && from != me && !self.blocked.contains(&from) {
+                    self.pending_reactions.push((from, emoji));
+                    if self._reaction_digest.is_none() {
+                        self.frame_commands.push(FrameCommand::Arm(Timer::ReactionDigest));
+                    }
+                }
+                if changed {
+                    self.save_history();
+                }
+                changed
+            }
+            Incoming::Activity { kind, from, room } => {
+                let same_room = room.as_deref().unwrap_or(DEFAULT_ROOM) == self.current_room;
+                if !same_room || from == *self.user.username.borrow() {
+                    return false;
+                }
+                self.frame_commands.push(FrameCommand::Arm(Timer::Activity(from, kind)));
+                true
+            }
+            Incoming::Edit { message_id, message, from } => {
+                self.remote_editing.remove(&message_id);
+                let changed = apply_edit(&mut self.messages, &mut self.search_index, &message_id, message, &from);
+                if changed {
+                    self.save_history();
+                }
+                changed
+            }
+            Incoming::Editing { message_id, active, from } => {
+                if from == *self.user.username.borrow() {
+                    return false;
+                }
+                if active {
+                    self.frame_commands.push(FrameCommand::Arm(Timer::Editing(message_id)));
+                    true
+                } else {
+                    self.remote_editing.remove(&message_id).is_some()
+                }
+            }
+            Incoming::Ack { id, recipients } => {
+                let counted = match recipients {
+                    Some(n) => self.recipients.insert(id.clone(), n) != Some(n),
+                    None => false,
+                };
+                self.mark_sent(&id) || counted
+            }
+            // Latency is only shown for the primary server.
+            Incoming::Pong { sent_at } if origin == self.wss[0].url => {
+                let latency = (now - sent_at).max(0.0) as u32;
+                self.latency_ms = Some(latency);
+                true
+            }
+            Incoming::Pong { .. } | Incoming::Ignored => false,
+        }
+    }
+
+    fn run_frame_commands(&mut self, ctx: &Context<Self>) {
+        for command in std::mem::take(&mut self.frame_commands) {
+            let link = ctx.link().clone();
+            match command {
+                FrameCommand::Emit(event) => ctx.props().on_event.emit(event),
+                FrameCommand::Toast(text) => self.toast(ctx, text),
+                FrameCommand::Arm(Timer::UsersDebounce) => {
+                    self._users_debounce = Some(Timeout::new(self.config.users_debounce_ms, move || {
+                        link.send_message(Msg::FlushUsers)
+                    }));
+                }
+                FrameCommand::Arm(Timer::ReactionDigest) => {
+                    self._reaction_digest = Some(Timeout::new(self.config.reaction_digest_ms, move || {
+                        link.send_message(Msg::FlushReactions)
+                    }));
+                }
+                FrameCommand::Arm(Timer::Activity(from, kind)) => {
+                    let ttl = activity_ttl_ms(&self.config, kind);
+                    let user = from.clone();
+                    let timeout = Timeout::new(ttl, move || link.send_message(Msg::ActivityExpired(user)));
+                    self.activity.insert(from, (kind, js_sys::Date::now(), timeout));
+                }
+                FrameCommand::Arm(Timer::Editing(id)) => {
+                    let message_id = id.clone();
+                    let timeout = Timeout::new(self.config.editing_ttl_ms, move || {
+                        link.send_message(Msg::EditingExpired(message_id))
+                    });
+                    self.remote_editing.insert(id, timeout);
+                }
+            }
+        }
+    }
+
+    // Returns whether anything in the sidebar changed.
+    fn apply_users(
+        &mut self,
+        origin: String,
+        names: Vec<String>,
+        details: Vec<UserDetails>,
+        now: f64,
+    ) -> bool {
+        let me = self.user.username.borrow().clone();
+        let (joined, left) = roster_changes(&self.users, &origin, &names, &me);
+        let room = Some(self.current_room.clone());
+        for name in joined {
+            if self.settings.join_toasts {
+                self.frame_commands.push(FrameCommand::Toast(format!("{} joined the chat", name)));
+            }
+            if self.settings.join_lines {
+                self.push_system(room.clone(), format!("{} joined the chat", name), None, now);
+            }
+        }
+        if self.settings.join_lines {
+            for name in left {
+                self.push_system(room.clone(), format!("{} left the chat", name), None, now);
+            }
+        }
+        let users = roster(&self.users, &origin, &names, &details, now, &self.config);
+        let unchanged = {
+            let current: Vec<&UserProfile> = self.users.iter().filter(|u| u.origin == origin).collect();
+            current.len() == users.len() && current.iter().zip(&users).all(|(a, b)| *a == b)
+        };
+        if unchanged {
+            return false;
+        }
+        // Each server only knows its own users.
+        self.users.retain(|u| u.origin != origin);
+        self.users.extend(users);
+        true
+    }
+
+    fn announce_activity(&mut self, kind: ActivityKind) {
+        let now = js_sys::Date::now();
+        if now - self.last_activity_sent < f64::from(self.config.activity_throttle_ms) {
+            return;
+        }
+        self.last_activity_sent = now;
+        let activity = ActivityData { kind, from: None };
+        self.send(WebSocketMessage {
+            message_type: MsgTypes::Activity,
+            data: Some(serde_json::to_string(&activity).unwrap()),
+            room: Some(self.current_room.clone()),
+            ..Default::default()
+        });
+    }
+
+    fn send_editing(&self, message_id: MessageId, active: bool) {
+        let editing = EditingData {
+            message_id,
+            active,
+            from: None,
+        };
+        self.send(WebSocketMessage {
+            message_type: MsgTypes::Editing,
+            data: Some(serde_json::to_string(&editing).unwrap()),
+            ..Default::default()
+        });
+    }
+
+    fn stop_editing(&mut self) {
+        if let Some(id) = self.editing.take() {
+            self.send_editing(id, false);
+        }
+    }
+
+    // Tells the server we have read up to the newest message in the room.
+    fn mark_read(&mut self) {
+        if gloo::utils::document().hidden() {
+            return;
+        }
+        let me = self.user.username.borrow().clone();
+        let latest = self
+            .messages
+            .iter()
+            .rev()
+            .find(|m| m.room() == self.current_room && m.kind == MessageKind::User && m.from != me)
+            .map(|m| m.id.clone());
+        let latest = match latest {
+            Some(id) if self.last_read_sent.as_ref() != Some(&id) => id,
+            _ => return,
+        };
+        let read = ReadData {
+            message_id: latest.clone(),
+            from: None,
+            time: None,
+        };
+        self.send(WebSocketMessage {
+            message_type: MsgTypes::Read,
+            data: Some(serde_json::to_string(&read).unwrap()),
+            room: Some(self.current_room.clone()),
+            ..Default::default()
+        });
+        self.last_read.insert(self.current_room.clone(), latest.clone());
+        if let Err(e) = LocalStorage::set(LAST_READ_STORAGE_KEY, &self.last_read) {
+            log::error!("failed to persist read positions: {:?}", e);
+        }
+        self.last_read_sent = Some(latest);
+    }
+
+    // Everyone who read this message or a later one in the same room, with
+    // the first time they did.
+    fn seen_by(&self, index: usize) -> Vec<(String, f64)> {
+        let room = self.messages[index].room();
+        let mut seen: HashMap<&str, f64> = HashMap::new();
+        for m in self.messages[index..].iter().filter(|m| m.room() == room) {
+            for (user, time) in self.receipts.get(&m.id).into_iter().flatten() {
+                let first = seen.entry(user.as_str()).or_insert(*time);
+                *first = first.min(*time);
+            }
+        }
+        let mut seen: Vec<(String, f64)> = seen.into_iter().map(|(u, t)| (u.to_string(), t)).collect();
+        seen.sort_by(|a, b| a.1.total_cmp(&b.1));
+        seen
+    }
+
+    // What `quoted` itself replied to, and so on up the chain, oldest first.
+    fn reply_ancestors(&self, quoted: &MessageData) -> Vec<&MessageData> {
+        let mut chain = vec![];
+        let mut next = quoted.reply_to.as_ref();
+        while let Some(id) = next {
+            if chain.len() == MAX_REPLY_DEPTH || id == &quoted.id {
+                break;
+            }
+            match self.messages.iter().find(|m| &m.id == id) {
+                Some(parent) if !chain.iter().any(|c: &&MessageData| c.id == parent.id) => {
+                    chain.push(parent);
+                    next = parent.reply_to.as_ref();
+                }
+                _ => break,
+            }
+        }
+        chain.reverse();
+        chain
+    }
+
+    // Index of the first message from someone else after the last one we read
+    // in `room`; nothing when we have never read the room.
+    fn first_unread(&self, room: &str) -> Option<usize> {
+        let last_read = self.last_read.get(room)?;
+        let me = self.user.username.borrow().clone();
+        let read_at = self.messages.iter().position(|m| &m.id == last_read)?;
+        self.messages
+            .iter()
+            .enumerate()
+            .skip(read_at + 1)
+            .find(|(_, m)| m.room() == room && m.thread_id.is_none() && m.kind == MessageKind::User && m.from != me)
+            .map(|(i, _)| i)
+    }
+
+    fn jump_to_unread(&mut self) {
+        let first = self.first_unread(&self.current_room);
+        self.unread_marker = first;
+        self.scroll_to = first.map(|i| self.messages[i].id.clone());
+    }
+
+    // A best guess at who got a message, until an ack says exactly.
+    fn online_recipients(&self) -> usize {
+        let me = self.user.username.borrow();
+        let mut online: Vec<&str> = self
+            .users
+            .iter()
+            .filter(|u| u.online && u.name != *me)
+            .map(|u| u.name.as_str())
+            .collect();
+        online.sort_unstable();
+        online.dedup();
+        online.len()
+    }
+
+    fn mark_sent(&mut self, id: &str) -> bool {
+        self.ack_timeouts.remove(id);
+        match self.delivery.get_mut(id) {
+            Some(status) if *status != Delivery::Sent => {
+                *status = Delivery::Sent;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Presence and identity have to be known to every server.
+    fn broadcast(&self, message: WebSocketMessage) {
+        for wss in &self.wss {
+            if let Err(e) = wss.send_json(&message) {
+                log::debug!("error sending to {}: {}", wss.url, e);
+            }
+        }
+    }
+
+    // Text the user has selected inside the message's bubble, if any.
+    fn selection_in(&self, id: &str) -> Option<String> {
+        let bubble = self.message_refs.get(id)?.cast::<Element>()?;
+        let selection = gloo::utils::window().get_selection().ok()??;
+        let anchor = selection.anchor_node()?;
+        if selection.is_collapsed() || !bubble.contains(Some(&anchor)) {
+            return None;
+        }
+        let text: String = selection.to_string().into();
+        let text = text.trim();
+        (!text.is_empty()).then(|| text.to_string())
+    }
+
+    fn all_failed(&self) -> bool {
+        self.connections.iter().all(|c| *c == ConnectionState::Failed)
+    }
+
+    fn origin_index(&self, origin: Option<&str>) -> usize {
+        origin
+            .and_then(|origin| self.wss.iter().position(|wss| wss.url == origin))
+            .unwrap_or(0)
+    }
+}
+
+impl Component for Chat {
+    type Message = Msg;
+    type Properties = ChatProps;
+
+    fn create(ctx: &Context<Self>) -> Self {
+        let (user, _) = ctx
+            .link()
+            .context::<User>(Callback::noop())
+            .expect("context to be set");
+        let (settings, settings_handle) = ctx
+            .link()
+            .context::<SettingsContext>(ctx.link().callback(Msg::SettingsChanged))
+            .expect("settings context to be set");
+        let config = ctx
+            .link()
+            .context::<ChatConfig>(Callback::noop())
+            .map(|(config, _)| config)
+            .unwrap_or_default();
+        let wss: Vec<WebsocketService> = (0..ctx.props().servers.len())
+            .map(|index| Self::connect(ctx, &config, index))
+            .collect();
+        let connections = vec![ConnectionState::Connecting; wss.len()];
+
+        let current_room = ctx.props().room.clone();
+        let mut rooms = vec![DEFAULT_ROOM.to_string()];
+        if current_room != DEFAULT_ROOM {
+            rooms.push(current_room.clone());
+        }
+
+        let visibility_listener = {
+            let link = ctx.link().clone();
+            EventListener::new(&gloo::utils::document(), "visibilitychange", move |_| {
+                link.send_message(Msg::VisibilityChanged(gloo::utils::document().hidden()))
+            })
+        };
+
+        let online_listeners = {
+            let window = gloo::utils::window();
+            let online = ctx.link().callback(|_| Msg::OnlineChanged(true));
+            let offline = ctx.link().callback(|_| Msg::OnlineChanged(false));
+            [
+                EventListener::new(&window, "online", move |_| online.emit(())),
+                EventListener::new(&window, "offline", move |_| offline.emit(())),
+            ]
+        };
+
+        // Last chance to keep what is in the composer before the page goes.
+        let unload_listener = {
+            let save = ctx.link().callback(|_| Msg::SaveDraft);
+            EventListener::new(&gloo::utils::window(), "beforeunload", move |_| save.emit(()))
+        };
+
+        // Ctrl/Cmd+K opens the room switcher, Ctrl/Cmd+E the emoji picker,
+        // Alt+Up/Down cycles rooms and
+        // 1-4 react with the quick reactions while not typing.
+        let keydown_listener = {
+            let link = ctx.link().clone();
+            EventListener::new(&gloo::utils::document(), "keydown", move |e| {
+                let e = match e.dyn_ref::<KeyboardEvent>() {
+                    Some(e) => e,
+                    None => return,
+                };
+                let typing = e
+                    .target()
+                    .and_then(|t| t.dyn_into::<HtmlElement>().ok())
+                    .is_some_and(|el| {
+                        matches!(el.tag_name().as_str(), "INPUT" | "TEXTAREA" | "SELECT")
+                            || el.is_content_editable()
+                    });
+                let modified = e.ctrl_key() || e.meta_key() || e.alt_key();
+                let key = e.key();
+                let quick_reaction = key
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| (1..=QUICK_REACTIONS.len()).contains(n));
+                let msg = match key.as_str() {
+                    "k" if e.ctrl_key() || e.meta_key() => Msg::ToggleRoomSwitcher,
+                    "e" if e.ctrl_key() || e.meta_key() => Msg::ToggleEmojiPicker,
+                    "f" | "F" if (e.ctrl_key() || e.meta_key()) && e.shift_key() => Msg::ToggleFocusMode,
+                    "Escape" if !typing => Msg::ExitFocusMode,
+                    "ArrowUp" if e.alt_key() => Msg::CycleRoom(-1),
+                    "ArrowDown" if e.alt_key() => Msg::CycleRoom(1),
+                    _ => match quick_reaction {
+                        Some(n) if !typing && !modified => Msg::QuickReact(n - 1),
+                        _ => return,
+                    },
+                };
+                e.prevent_default();
+                link.send_message(msg);
+            })
+        };
+
+        let older_observer = ctx.props().paginate_history.then(|| {
+            let link = ctx.link().clone();
+            let callback: ObserverCallback = Closure::wrap(Box::new(move |entries: js_sys::Array, _| {
+                if entries
+                    .iter()
+                    .any(|entry| entry.unchecked_into::<IntersectionObserverEntry>().is_intersecting())
+                {
+                    link.send_message(Msg::LoadOlder);
+                }
+            }));
+            IntersectionObserver::new(callback.as_ref().unchecked_ref())
+                .map(|observer| (observer, callback))
+                .map_err(|e| log::error!("no IntersectionObserver, older history stays unloaded: {:?}", e))
+                .ok()
+        }).flatten();
+
+        let twemoji = settings.twemoji;
+        let sounds = config
+            .sounds
+            .iter()
+            .filter_map(|(_, url)| {
+                let audio = HtmlAudioElement::new_with_src(url).ok()?;
+                audio.set_preload("auto");
+                Some((url.clone(), audio))
+            })
+            .collect();
+
+        let mut chat = Self {
+            user,
+            users: vec![],
+            profiles: HashMap::new(),
+            config,
+            messages: vec![],
+            chat_input: NodeRef::default(),
+            wss,
+            _producer: EventBus::bridge(ctx.link().callback(Msg::HandleMsg)),
+            latency_ms: None,
+            pending_attachments: vec![],
+            attachment_error: None,
+            readers: HashMap::new(),
+            next_reader_id: 0,
+            renaming: false,
+            rename_input: NodeRef::default(),
+            rename_error: None,
+            blocked: LocalStorage::get(BLOCKED_STORAGE_KEY).unwrap_or_default(),
+            replying_to: None,
+            reply_quote: None,
+            open_thread: None,
+            thread_input: NodeRef::default(),
+            editing: None,
+            edit_input: NodeRef::default(),
+            remote_editing: HashMap::new(),
+            drafts: LocalStorage::get(DRAFTS_STORAGE_KEY).unwrap_or_default(),
+            composer_history: ComposerHistory::default(),
+            show_preview: false,
+            mention: None,
+            mention_selected: 0,
+            message_refs: HashMap::new(),
+            highlighted: None,
+            hovered: None,
+            away_ids: HashSet::new(),
+            delivery: HashMap::new(),
+            recipients: HashMap::new(),
+            receipts: HashMap::new(),
+            last_read_sent: None,
+            activity: HashMap::new(),
+            last_activity_sent: 0.0,
+            ack_timeouts: HashMap::new(),
+            expanded: HashSet::new(),
+            loaded_media: HashSet::new(),
+            expanded_context: HashSet::new(),
+            _highlight_timeout: None,
+            settings,
+            _settings_handle: settings_handle,
+            show_settings: false,
+            current_room,
+            rooms,
+            new_room_input: NodeRef::default(),
+            search_index: SearchIndex::default(),
+            search_query: String::new(),
+            ever_open: vec![false; connections.len()],
+            reconnect_at: vec![None; connections.len()],
+            _countdown: None,
+            show_traffic: false,
+            _traffic_refresh: None,
+            connections,
+            gaps: vec![],
+            renderers: default_renderers(twemoji),
+            sounds,
+            toasts: vec![],
+            announcements: vec![],
+            dismissed_announcements: LocalStorage::get(DISMISSED_STORAGE_KEY).unwrap_or_default(),
+            pending_reactions: vec![],
+            _reaction_digest: None,
+            next_toast_id: 0,
+            user_filter: String::new(),
+            sidebar_user_limit: ctx.props().max_sidebar_users,
+            show_stats: false,
+            stats_sort: StatsSort::Count,
+            session_started: js_sys::Date::now(),
+            gif_picker_open: false,
+            emoji_picker_open: false,
+            emoji_selected: 0,
+            emoji_picker: NodeRef::default(),
+            emoji_picker_focus: false,
+            gif_input: NodeRef::default(),
+            gif_query: String::new(),
+            gif_results: vec![],
+            gif_loading: false,
+            gif_error: None,
+            unread_marker: None,
+            last_read: LocalStorage::get(LAST_READ_STORAGE_KEY).unwrap_or_default(),
+            scroll_to: None,
+            pending_permalink: None,
+            message_menu: None,
+            message_list: NodeRef::default(),
+            saved_scroll: None,
+            older_sentinel: NodeRef::default(),
+            older_observer,
+            observed_sentinel: None,
+            loading_older: false,
+            history_exhausted: HashSet::new(),
+            older_anchor: None,
+            restore_view: false,
+            _visibility_listener: visibility_listener,
+            online: gloo::utils::window().navigator().on_line(),
+            _online_listeners: online_listeners,
+            loaded_users: false,
+            pending_users: HashMap::new(),
+            _users_debounce: None,
+            frame_commands: vec![],
+            room_switcher_open: false,
+            focus_mode: false,
+            room_switcher_query: String::new(),
+            room_switcher_input: NodeRef::default(),
+            _keydown_listener: keydown_listener,
+            _unload_listener: unload_listener,
+        };
+        let history: Vec<MessageData> =
+            LocalStorage::get(HISTORY_STORAGE_KEY).unwrap_or_default();
+        for message in history {
+            chat.push_message(message);
+        }
+        chat.jump_to_unread();
+        chat.mark_read();
+        if let Some(id) = permalink(ctx) {
+            ctx.link().send_message(Msg::HighlightMessage(id));
+        }
+        chat
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::HandleMsg(Frame { origin, data }) => {
+                let hidden = gloo::utils::document().hidden();
+                let changed = self.handle_frame(origin, &data, js_sys::Date::now(), hidden);
+                self.run_frame_commands(ctx);
+                changed
+            }
+            Msg::SubmitMessage => {
+                let input = self.chat_input.cast::<HtmlTextAreaElement>();
+                if let Some(input) = input {
+                    if !input.value().trim().is_empty() {
+                        let reply_to = self.replying_to.take();
+                        let quote = self.reply_quote.take();
+                        self.send_chat(ctx, input.value(), reply_to, quote, None, None);
+                    }
+                    input.set_value("");
+                    autogrow(&input, self.config.composer_max_lines);
+                    self.composer_history.reset("");
+                    if self.drafts.remove(&self.current_room).is_some() {
+                        self.save_drafts();
+                    }
+                    // Clicking send moves focus to the button; hand it back so
+                    // typing can carry on.
+                    let _ = input.focus();
+                };
+                self.unread_marker = None;
+                self.attachment_error = None;
+                for attachment in std::mem::take(&mut self.pending_attachments) {
+                    let meta = AttachmentMeta {
+                        name: attachment.name,
+                        mime_type: attachment.mime_type,
+                        size: attachment.size,
+                    };
+                    self.send_chat(ctx, attachment.data_url, None, None, None, Some(meta));
+                }
+                true
+            }
+            Msg::ActivityExpired(user) => self.activity.remove(&user).is_some(),
+            Msg::AckTimeout(id) => {
+                self.ack_timeouts.remove(&id);
+                match self.delivery.get_mut(&id) {
+                    Some(status) if *status == Delivery::Sending => {
+                        *status = Delivery::Failed;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            Msg::DraftChanged(text) => {
+                self.composer_history.record(&text, true);
+                if !text.is_empty() {
+                    self.announce_activity(ActivityKind::Typing);
+                }
+                if text.is_empty() {
+                    self.drafts.remove(&self.current_room);
+                } else {
+                    self.drafts.insert(self.current_room.clone(), text);
+                }
+                self.save_drafts();
+                self.show_preview
+            }
+            Msg::MentionChanged(mention) => {
+                if mention == self.mention {
+                    return false;
+                }
+                self.mention = mention;
+                self.mention_selected = 0;
+                true
+            }
+            Msg::ComposerUndo => match self.composer_history.undo().map(str::to_string) {
+                Some(text) => {
+                    self.set_composer(&text);
+                    true
+                }
+                None => false,
+            },
+            Msg::ComposerRedo => match self.composer_history.redo().map(str::to_string) {
+                Some(text) => {
+                    self.set_composer(&text);
+                    true
+                }
+                None => false,
+            },
+            Msg::MentionKey(key) => {
+                let candidates = self.mention_candidates();
+                if candidates.is_empty() {
+                    return false;
+                }
+                match key.as_str() {
+                    "ArrowDown" => self.mention_selected = (self.mention_selected + 1) % candidates.len(),
+                    "ArrowUp" => {
+                        self.mention_selected = (self.mention_selected + candidates.len() - 1) % candidates.len()
+                    }
+                    "Escape" => self.mention = None,
+                    _ => {
+                        let name = &candidates[self.mention_selected.min(candidates.len() - 1)];
+                        return self.insert_mention(name);
+                    }
+                }
+                true
+            }
+            Msg::PickMention(name) => self.insert_mention(&name),
+            Msg::SaveDraft => {
+                self.save_current_draft();
+                false
+            }
+            Msg::TogglePreview => {
+                self.show_preview = !self.show_preview;
+                true
+            }
+            Msg::FileSelected(files) => {
+                self.attachment_error = None;
+                let mut accepted = false;
+                for file in files {
+                    let name = file.name();
+                    let mime_type = file.raw_mime_type();
+                    let size = file.size();
+                    if let Some(reason) = attachment_rejection(&self.config, &mime_type, size) {
+                        self.attachment_error = Some(format!("{}: {}", name, reason));
+                        continue;
+                    }
+                    if !accepted {
+                        accepted = true;
+                        self.announce_activity(ActivityKind::Uploading);
+                    }
+                    let link = ctx.link().clone();
+                    let id = self.next_reader_id;
+                    self.next_reader_id += 1;
+                    let reader = gloo::file::callbacks::read_as_data_url(&file, move |result| match result {
+                        Ok(data_url) => link.send_message(Msg::AttachmentLoaded(id, Attachment {
+                            name,
+                            mime_type,
+                            size,
+                            data_url,
+                        })),
+                        Err(e) => log::error!("failed to read attachment: {:?}", e),
+                    });
+                    self.readers.insert(id, reader);
+                }
+                self.attachment_error.is_some()
+            }
+            Msg::AttachmentLoaded(id, attachment) => {
+                self.readers.remove(&id);
+                self.pending_attachments.push(attachment);
+                true
+            }
+            Msg::RemoveAttachment(index) => {
+                self.attachment_error = None;
+                if index < self.pending_attachments.len() {
+                    self.pending_attachments.remove(index);
+                }
+                true
+            }
+            Msg::ToggleRename => {
+                self.renaming = !self.renaming;
+                self.rename_error = None;
+                true
+            }
+            Msg::SubmitRename => {
+                if let Some(input) = self.rename_input.cast::<HtmlInputElement>() {
+                    let name = input.value().trim().to_string();
+                    if name.is_empty() || name == *self.user.username.borrow() {
+                        return false;
+                    }
+                    self.broadcast(WebSocketMessage {
+                        message_type: MsgTypes::Rename,
+                        data: Some(name),
+                        ..Default::default()
+                    });
+                }
+                false
+            }
+            Msg::ToggleBlock(name) => {
+                if !self.blocked.remove(&name) {
+                    self.blocked.insert(name);
+                }
+                if let Err(e) = LocalStorage::set(BLOCKED_STORAGE_KEY, &self.blocked) {
+                    log::error!("failed to persist blocked users: {:?}", e);
+                }
+                true
+            }
+            Msg::StartEdit(id) => {
+                self.stop_editing();
+                self.send_editing(id.clone(), true);
+                self.editing = Some(id);
+                true
+            }
+            Msg::SubmitEdit => {
+                let text = match self.edit_input.cast::<HtmlInputElement>() {
+                    Some(input) => input.value().trim().to_string(),
+                    None => return false,
+                };
+                let id = match self.editing.take() {
+                    Some(id) => id,
+                    None => return false,
+                };
+                let unchanged = self.messages.iter().any(|m| m.id == id && m.message == text);
+                if text.is_empty() || unchanged {
+                    self.send_editing(id, false);
+                    return true;
+                }
+                // The edit frame also ends the "editing…" marker for others.
+                let edit = EditData {
+                    message_id: id,
+                    message: text,
+                    from: None,
+                };
+                self.send(WebSocketMessage {
+                    message_type: MsgTypes::Edit,
+                    data: Some(serde_json::to_string(&edit).unwrap()),
+                    ..Default::default()
+                });
+                true
+            }
+            Msg::CancelEdit => {
+                self.stop_editing();
+                true
+            }
+            Msg::EditingExpired(id) => self.remote_editing.remove(&id).is_some(),
+            Msg::StartReply(id) => {
+                self.reply_quote = self.selection_in(&id);
+                self.replying_to = Some(id);
+                true
+            }
+            Msg::CancelReply => {
+                self.replying_to = None;
+                self.reply_quote = None;
+                true
+            }
+            Msg::OpenThread(id) => {
+                self.open_thread = Some(id);
+                true
+            }
+            Msg::CloseThread => self.open_thread.take().is_some(),
+            Msg::SubmitThreadReply => {
+                let (input, root) = match (self.thread_input.cast::<HtmlInputElement>(), self.open_thread.clone()) {
+                    (Some(input), Some(root)) => (input, root),
+                    _ => return false,
+                };
+                let text = input.value();
+                if text.trim().is_empty() {
+                    return false;
+                }
+                input.set_value("");
+                self.send_chat(ctx, text, None, None, Some(root), None);
+                true
+            }
+            Msg::ScrollToMessage(id) => {
+                let element = self
+                    .message_refs
+                    .get(&id)
+                    .and_then(|r| r.cast::<Element>());
+                match element {
+                    Some(element) => {
+                        let mut options = ScrollIntoViewOptions::new();
+                        options.behavior(ScrollBehavior::Smooth);
+                        element.scroll_into_view_with_scroll_into_view_options(&options);
+                        let link = ctx.link().clone();
+                        self._highlight_timeout = Some(Timeout::new(self.config.highlight_duration_ms, move || {
+                            link.send_message(Msg::ClearHighlight)
+                        }));
+                        self.highlighted = Some(id);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            Msg::ClearHighlight => {
+                self.highlighted = None;
+                self._highlight_timeout = None;
+                true
+            }
+            Msg::HighlightMessage(id) => {
+                self.pending_permalink = Some(id);
+                true
+            }
+            Msg::OpenMessageMenu(id, x, y) => {
+                self.message_menu = Some((id, x, y));
+                true
+            }
+            Msg::CloseMessageMenu => self.message_menu.take().is_some(),
+            Msg::CopyMessageLink(id) => {
+                self.message_menu = None;
+                let origin = gloo::utils::window().location().origin().unwrap_or_default();
+                let link = format!(
+                    "{}/chat/{}?msg={}",
+                    origin,
+                    js_sys::encode_uri_component(&self.current_room),
+                    js_sys::encode_uri_component(&id)
+                );
+                ctx.link().send_future(async move {
+                    match copy_to_clipboard(&link).await {
+                        Ok(()) => Msg::MessageLinkCopied(true),
+                        Err(e) => {
+                            log::error!("failed to copy message link: {:?}", e);
+                            Msg::MessageLinkCopied(false)
+                        }
+                    }
+                });
+                true
+            }
+            Msg::MessageLinkCopied(copied) => {
+                let text = if copied {
+                    "Link to message copied"
+                } else {
+                    "Couldn't copy the message link"
+                };
+                self.toast(ctx, text.to_string());
+                true
+            }
+            Msg::ExportHistory => {
+                let history: Vec<&MessageData> = self
+                    .messages
+                    .iter()
+                    .filter(|m| m.room() == self.current_room && m.kind == MessageKind::User)
+                    .collect();
+                let json = serde_json::to_string_pretty(&history).unwrap();
+                let href = format!(
+                    "data:application/json;charset=utf-8,{}",
+                    js_sys::encode_uri_component(&json)
+                );
+                let document = gloo::utils::document();
+                if let Ok(link) = document.create_element("a") {
+                    let _ = link.set_attribute("href", &href);
+                    let _ = link.set_attribute(
+                        "download",
+                        &format!("yewchat-{}.json", self.current_room),
+                    );
+                    if let Some(link) = link.dyn_ref::<HtmlElement>() {
+                        link.click();
+                    }
+                }
+                false
+            }
+            Msg::VisibilityChanged(hidden) => {
+                if hidden {
+                    self.unread_marker = Some(self.messages.len());
+                    false
+                } else if self.unread_marker == Some(self.messages.len()) {
+                    // Nothing arrived while away, so there is nothing to mark.
+                    self.unread_marker = None;
+                    false
+                } else {
+                    self.mark_read();
+                    true
+                }
+            }
+            Msg::OnlineChanged(online) => {
+                self.online = online;
+                true
+            }
+            Msg::FlushUsers => {
+                self._users_debounce = None;
+                let mut changed = false;
+                for (origin, (names, details)) in std::mem::take(&mut self.pending_users) {
+                    changed |= self.apply_users(origin, names, details, js_sys::Date::now());
+                }
+                self.run_frame_commands(ctx);
+                changed
+            }
+            Msg::FlushReactions => {
+                self.flush_reactions(ctx);
+                true
+            }
+            Msg::DismissAnnouncement(id) => {
+                self.announcements.retain(|(known, _)| *known != id);
+                self.dismissed_announcements.insert(id);
+                if let Err(e) = LocalStorage::set(DISMISSED_STORAGE_KEY, &self.dismissed_announcements) {
+                    log::error!("failed to persist dismissed announcements: {:?}", e);
+                }
+                true
+            }
+            Msg::DismissToast(id) => {
+                self.toasts.retain(|t| t.id != id);
+                true
+            }
+            Msg::FilterUsers(filter) => {
+                self.user_filter = filter;
+                self.sidebar_user_limit = ctx.props().max_sidebar_users;
+                true
+            }
+            Msg::ToggleStats => {
+                self.show_stats = !self.show_stats;
+                true
+            }
+            Msg::SortStats(sort) => {
+                self.stats_sort = sort;
+                true
+            }
+            Msg::ShowMoreUsers => {
+                self.sidebar_user_limit += ctx.props().max_sidebar_users;
+                true
+            }
+            Msg::ToggleExpanded(id) => {
+                if !self.expanded.remove(&id) {
+                    self.expanded.insert(id);
+                }
+                true
+            }
+            Msg::LoadMedia(id) => self.loaded_media.insert(id),
+            Msg::LoadOlder => {
+                if self.loading_older || self.history_exhausted.contains(&self.current_room) {
+                    return false;
+                }
+                let before = self
+                    .messages
+                    .iter()
+                    .filter(|m| m.kind == MessageKind::User && m.room() == self.current_room)
+                    .find_map(|m| m.time)
+                    .unwrap_or_else(js_sys::Date::now);
+                let request = WebSocketMessage {
+                    message_type: MsgTypes::History,
+                    room: Some(self.current_room.clone()),
+                    before: Some(before),
+                    limit: Some(self.config.history_page_size),
+                    ..Default::default()
+                };
+                // Offline: the sentinel is re-observed on reconnect and asks again.
+                self.loading_older = self.try_send_to(0, &request).is_ok();
+                self.loading_older
+            }
+            Msg::ToggleContext(id) => {
+                if !self.expanded_context.remove(&id) {
+                    self.expanded_context.insert(id);
+                }
+                true
+            }
+            Msg::ToggleShowOffline => {
+                let mut next = (*self.settings).clone();
+                next.show_offline = !next.show_offline;
+                next.save();
+                self.settings.set(next);
+                false
+            }
+            Msg::CycleRoomNotify(room) => {
+                let mut next = (*self.settings).clone();
+                let level = next.notify_level(&room).next();
+                if level == NotifyLevel::All {
+                    next.room_notify.remove(&room);
+                } else {
+                    next.room_notify.insert(room, level);
+                }
+                next.save();
+                // Re-renders through `SettingsChanged`.
+                self.settings.set(next);
+                false
+            }
+            Msg::ToggleGifPicker => {
+                self.gif_picker_open = !self.gif_picker_open;
+                true
+            }
+            Msg::ToggleEmojiPicker => {
+                self.emoji_picker_open = !self.emoji_picker_open;
+                self.emoji_selected = 0;
+                self.emoji_picker_focus = true;
+                true
+            }
+            Msg::EmojiPickerKey(key) => {
+                let last = PICKER_EMOJI.len() - 1;
+                let selected = self.emoji_selected;
+                self.emoji_selected = match key.as_str() {
+                    "ArrowLeft" => selected.saturating_sub(1),
+                    "ArrowRight" => (selected + 1).min(last),
+                    "ArrowUp" => selected.checked_sub(PICKER_COLUMNS).unwrap_or(selected),
+                    "ArrowDown" => (selected + PICKER_COLUMNS).min(last),
+                    "Home" => 0,
+                    "End" => last,
+                    "Enter" => {
+                        ctx.link().send_message(Msg::InsertEmoji(PICKER_EMOJI[selected]));
+                        return false;
+                    }
+                    "Escape" => {
+                        ctx.link().send_message(Msg::ToggleEmojiPicker);
+                        return false;
+                    }
+                    _ => return false,
+                };
+                self.emoji_selected != selected
+            }
+            Msg::InsertEmoji(emoji) => {
+                self.insert_at_caret(emoji);
+                self.emoji_picker_open = false;
+                self.emoji_picker_focus = true;
+                true
+            }
+            Msg::SearchGifs => {
+                let query = match self.gif_input.cast::<HtmlInputElement>() {
+                    Some(input) => input.value().trim().to_string(),
+                    None => return false,
+                };
+                if query.is_empty() {
+                    return false;
+                }
+                if self.settings.gif_api_key.is_empty() {
+                    self.gif_error = Some("Add a Tenor API key in settings to search GIFs.".into());
+                    return true;
+                }
+                self.gif_query = query.clone();
+                self.gif_loading = true;
+                self.gif_error = None;
+                let api_key = self.settings.gif_api_key.clone();
+                ctx.link().send_future(async move {
+                    let result = gif::search(&api_key, &query).await;
+                    Msg::GifsLoaded(query, result)
+                });
+                true
+            }
+            Msg::GifsLoaded(query, result) => {
+                // A newer search is already in flight.
+                if query != self.gif_query {
+                    return false;
+                }
+                self.gif_loading = false;
+                match result {
+                    Ok(gifs) => {
+                        self.gif_error = gifs.is_empty().then(|| format!("No GIFs found for \"{}\"", query));
+                        self.gif_results = gifs;
+                    }
+                    Err(e) => {
+                        log::error!("gif search failed: {}", e);
+                        self.gif_error = Some("GIF search failed.".into());
+                        self.gif_results.clear();
+                    }
+                }
+                true
+            }
+            Msg::SendGif(url) => {
+                let reply_to = self.replying_to.take();
+                let quote = self.reply_quote.take();
+                self.send_chat(ctx, url, reply_to, quote, None, None);
+                self.gif_picker_open = false;
+                true
+            }
+            Msg::Interacted => {
+                if self.away_ids.is_empty() || gloo::utils::document().hidden() {
+                    return false;
+                }
+                self.away_ids.clear();
+                true
+            }
+            Msg::DismissUnread => {
+                self.unread_marker = None;
+                true
+            }
+            Msg::CopyInvite => {
+                let origin = gloo::utils::window().location().origin().unwrap_or_default();
+                let link = format!(
+                    "{}/chat/{}",
+                    origin,
+                    js_sys::encode_uri_component(&self.current_room)
+                );
+                ctx.link().send_future(async move {
+                    match copy_to_clipboard(&link).await {
+                        Ok(()) => Msg::InviteCopied(true),
+                        Err(e) => {
+                            log::error!("failed to copy invite link: {:?}", e);
+                            Msg::InviteCopied(false)
+                        }
+                    }
+                });
+                false
+            }
+            Msg::InviteCopied(copied) => {
+                let text = if copied {
+                    format!("Invite link to #{} copied", self.current_room)
+                } else {
+                    "Couldn't copy the invite link".to_string()
+                };
+                self.toast(ctx, text);
+                true
+            }
+            Msg::ToggleSettings => {
+                self.show_settings = !self.show_settings;
+                true
+            }
+            Msg::SettingsChanged(settings) => {
+                if settings.twemoji != self.settings.twemoji {
+                    self.renderers = default_renderers(settings.twemoji);
+                }
+                self.settings = settings;
+                true
+            }
+            Msg::SwitchRoom(room) => {
+                if room != self.current_room {
+                    if let Some(history) = ctx.link().history() {
+                        history.push(Route::Room { room });
+                    }
+                }
+                std::mem::take(&mut self.room_switcher_open)
+            }
+            Msg::SubmitNewRoom => {
+                if let Some(input) = self.new_room_input.cast::<HtmlInputElement>() {
+                    let room = input.value().trim().to_lowercase();
+                    input.set_value("");
+                    if !room.is_empty() {
+                        ctx.link().send_message(Msg::SwitchRoom(room));
+                    }
+                }
+                false
+            }
+            Msg::CycleRoom(step) => {
+                let len = self.rooms.len() as isize;
+                let current = self
+                    .rooms
+                    .iter()
+                    .position(|r| *r == self.current_room)
+                    .unwrap_or(0) as isize;
+                let next = (current + step).rem_euclid(len) as usize;
+                ctx.link().send_message(Msg::SwitchRoom(self.rooms[next].clone()));
+                false
+            }
+            Msg::ToggleRoomSwitcher => {
+                self.room_switcher_open = !self.room_switcher_open;
+                self.room_switcher_query.clear();
+                true
+            }
+            Msg::ToggleFocusMode => {
+                self.focus_mode = !self.focus_mode;
+                true
+            }
+            Msg::ExitFocusMode => std::mem::take(&mut self.focus_mode),
+            Msg::ToggleTraffic => {
+                self.show_traffic = !self.show_traffic;
+                // The log lives in the services, so poll it while it's on screen.
+                self._traffic_refresh = self.show_traffic.then(|| {
+                    let link = ctx.link().clone();
+                    Interval::new(1_000, move || link.send_message(Msg::RefreshTraffic))
+                });
+                true
+            }
+            Msg::RefreshTraffic => self.show_traffic,
+            Msg::FilterRoomSwitcher(query) => {
+                self.room_switcher_query = query;
+                true
+            }
+            Msg::Search(query) => {
+                self.search_query = query;
+                true
+            }
+            Msg::ConnectionChanged(index, state) => {
+                let was_down = self.all_failed();
+                let server = self.wss[index].url.clone();
+                match state {
+                    ConnectionState::Open => ctx.props().on_event.emit(ChatEvent::Connected { server }),
+                    ConnectionState::Reconnecting { attempt, .. } => {
+                        ctx.props().on_event.emit(ChatEvent::Reconnecting { server, attempt })
+                    }
+                    ConnectionState::Failed => {
+                        ctx.props().on_event.emit(ChatEvent::ConnectionFailed { server })
+                    }
+                    ConnectionState::Connecting => {}
+                }
+                if state == ConnectionState::Open {
+                    self.register(index);
+                    self.observed_sentinel = None;
+                    if std::mem::replace(&mut self.ever_open[index], true) {
+                        let text = if self.wss.len() > 1 {
+                            format!("Reconnected to {}", self.wss[index].url)
+                        } else {
+                            "Reconnected".to_string()
+                        };
+                        self.push_toast(ctx, text, true);
+                        if self.gaps.last() != Some(&self.messages.len()) {
+                            self.gaps.push(self.messages.len());
+                        }
+                        if ctx.props().backfill_history {
+                            let since = self
+                                .messages
+                                .iter()
+                                .rev()
+                                .find(|m| m.room() == self.current_room)
+                                .and_then(|m| m.time);
+                            self.send_to(index, WebSocketMessage {
+                                message_type: MsgTypes::History,
+                                data: since.map(|t| t.to_string()),
+                                room: Some(self.current_room.clone()),
+                                ..Default::default()
+                            });
+                        }
+                    }
+                } else if index == 0 {
+                    self.latency_ms = None;
+                }
+                self.connections[index] = state;
+                // The failure screen replaces the whole chat pane, so keep
+                // what is needed to put the composer and list back as they were.
+                match (was_down, self.all_failed()) {
+                    (false, true) => {
+                        self.saved_scroll = self.message_list.cast::<Element>().map(|list| list.scroll_top());
+                    }
+                    (true, false) => self.restore_view = true,
+                    _ => {}
+                }
+                self.reconnect_at[index] = match state {
+                    ConnectionState::Reconnecting { delay_ms, .. } => {
+                        Some(js_sys::Date::now() + f64::from(delay_ms))
+                    }
+                    _ => None,
+                };
+                if self.reconnect_at.iter().any(Option::is_some) {
+                    if self._countdown.is_none() {
+                        let link = ctx.link().clone();
+                        self._countdown = Some(Interval::new(1_000, move || {
+                            link.send_message(Msg::CountdownTick)
+                        }));
+                    }
+                } else {
+                    self._countdown = None;
+                }
+                true
+            }
+            Msg::ReconnectNow(index) => {
+                self.wss[index].reconnect_now();
+                false
+            }
+            Msg::CountdownTick => true,
+            Msg::RetryConnection => {
+                for index in 0..self.wss.len() {
+                    if self.connections[index] == ConnectionState::Failed {
+                        self.wss[index] = Self::connect(ctx, &self.config, index);
+                    }
+                }
+                true
+            }
+            Msg::HoverMessage(id) => {
+                self.hovered = id;
+                false
+            }
+            Msg::QuickReact(index) => {
+                let target = self.hovered.clone().or_else(|| {
+                    self.messages
+                        .iter()
+                        .rev()
+                        .find(|m| {
+                            m.room() == self.current_room && m.kind == MessageKind::User && !self.blocked.contains(&m.from)
+                        })
+                        .map(|m| m.id.clone())
+                });
+                if let Some(id) = target {
+                    ctx.link().send_message(Msg::React(id, QUICK_REACTIONS[index].to_string()));
+                }
+                false
+            }
+            Msg::React(message_id, emoji) => {
+                let message = self.messages.iter().find(|m| m.id == message_id);
+                // Reacting again with the same emoji takes it back.
+                let me = self.user.username.borrow().clone();
+                let remove = message
+                    .and_then(|m| m.reactions.get(&emoji))
+                    .is_some_and(|users| users.contains(&me));
+                // Reactions go back to the server the message came from.
+                let index = self.origin_index(message.and_then(|m| m.origin.as_deref()));
+                let reaction = ReactionData {
+                    message_id,
+                    emoji,
+                    from: None,
+                    remove,
+                };
+                self.send_to(index, WebSocketMessage {
+                    message_type: MsgTypes::Reaction,
+                    data: Some(serde_json::to_string(&reaction).unwrap()),
+                    ..Default::default()
+                });
+                false
+            }
+        }
+    }
+
+    fn rendered(&mut self, ctx: &Context<Self>, first_render: bool) {
+        if first_render || std::mem::take(&mut self.restore_view) {
+            self.restore_draft();
+            if let (Some(list), Some(top)) = (self.message_list.cast::<Element>(), self.saved_scroll.take()) {
+                list.set_scroll_top(top);
+            }
+        }
+        if let Some(id) = self.scroll_to.take() {
+            if let Some(element) = self.message_refs.get(&id).and_then(|r| r.cast::<Element>()) {
+                element.scroll_into_view();
+            }
+        }
+        let permalink_shown = self
+            .pending_permalink
+            .as_ref()
+            .and_then(|id| self.message_refs.get(id))
+            .is_some_and(|r| r.cast::<Element>().is_some());
+        if permalink_shown {
+            if let Some(id) = self.pending_permalink.take() {
+                ctx.link().send_message(Msg::ScrollToMessage(id));
+            }
+        }
+        if self.room_switcher_open {
+            if let Some(input) = self.room_switcher_input.cast::<HtmlInputElement>() {
+                let _ = input.focus();
+            }
+        }
+        if let (Some(list), Some(from_bottom)) = (self.message_list.cast::<Element>(), self.older_anchor.take()) {
+            list.set_scroll_top(list.scroll_height() - from_bottom);
+        }
+        if let Some((observer, _)) = &self.older_observer {
+            let sentinel = self.older_sentinel.cast::<Element>();
+            if sentinel != self.observed_sentinel {
+                observer.disconnect();
+                if let Some(sentinel) = &sentinel {
+                    observer.observe(sentinel);
+                }
+                self.observed_sentinel = sentinel;
+            }
+        }
+        if std::mem::take(&mut self.emoji_picker_focus) {
+            let target = if self.emoji_picker_open { &self.emoji_picker } else { &self.chat_input };
+            if let Some(element) = target.cast::<HtmlElement>() {
+                let _ = element.focus();
+            }
+        }
+    }
+
+    // The observer would otherwise call into its callback after it is freed.
+    // The services are dropped next, on purpose before the event bus bridge:
+    // each one aborts its connection task, which closes the socket and
+    // cancels any pending reconnect, so nothing new reaches the bus. The
+    // bridge goes with the remaining fields once this returns.
+    fn destroy(&mut self, _ctx: &Context<Self>) {
+        if let Some((observer, _)) = &self.older_observer {
+            observer.disconnect();
+        }
+        self.wss.clear();
+    }
+
+    fn changed(&mut self, ctx: &Context<Self>) -> bool {
+        if let Some(id) = permalink(ctx) {
+            ctx.link().send_message(Msg::HighlightMessage(id));
+        }
+        let room = ctx.props().room.clone();
+        if room == self.current_room {
+            return true;
+        }
+        self.broadcast(WebSocketMessage {
+            message_type: MsgTypes::Leave,
+            data: Some(self.current_room.clone()),
+            ..Default::default()
+        });
+        self.broadcast(WebSocketMessage {
+            message_type: MsgTypes::Join,
+            data: Some(room.clone()),
+            ..Default::default()
+        });
+        if !self.rooms.contains(&room) {
+            self.rooms.push(room.clone());
+        }
+        self.stop_editing();
+        self.current_room = room;
+        self.replying_to = None;
+        self.reply_quote = None;
+        self.open_thread = None;
+        self.jump_to_unread();
+        self.mark_read();
+        self.activity.clear();
+        self.loading_older = false;
+        self.observed_sentinel = None;
+        self.restore_draft();
+        true
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let submit = ctx.link().callback(|_| Msg::SubmitMessage);
+        let now = js_sys::Date::now();
+        let max_lines = self.config.composer_max_lines;
+        let on_draft = ctx.link().batch_callback(move |e: InputEvent| {
+            let input: HtmlTextAreaElement = e.target_unchecked_into();
+            autogrow(&input, max_lines);
+            let value = input.value();
+            let mention = input
+                .selection_start()
+                .ok()
+                .flatten()
+                .and_then(|caret| MentionToken::at_caret(&value, caret));
+            vec![Msg::DraftChanged(value), Msg::MentionChanged(mention)]
+        });
+        let mention_candidates = self.mention_candidates();
+        let clipboard = platform::clipboard_available();
+        let on_composer_key = {
+            let open = !mention_candidates.is_empty();
+            let send_key = self.settings.send_key;
+            ctx.link().batch_callback(move |e: KeyboardEvent| {
+                let key = e.key();
+                let handled = matches!(key.as_str(), "ArrowDown" | "ArrowUp" | "Enter" | "Tab" | "Escape");
+                if open && handled {
+                    e.prevent_default();
+                    return Some(Msg::MentionKey(key));
+                }
+                if (e.ctrl_key() || e.meta_key()) && !e.alt_key() {
+                    let history = match key.to_lowercase().as_str() {
+                        "z" if e.shift_key() => Some(Msg::ComposerRedo),
+                        "z" => Some(Msg::ComposerUndo),
+                        "y" => Some(Msg::ComposerRedo),
+                        _ => None,
+                    };
+                    if history.is_some() {
+                        e.prevent_default();
+                        return history;
+                    }
+                }
+                let send = key == "Enter"
+                    && !e.is_composing()
+                    && match send_key {
+                        SendKey::Enter => !e.shift_key(),
+                        SendKey::CtrlEnter => e.ctrl_key() || e.meta_key(),
+                    };
+                send.then(|| {
+                    e.prevent_default();
+                    Msg::SubmitMessage
+                })
+            })
+        };
+        let toggle_rename = ctx.link().callback(|_| Msg::ToggleRename);
+        let toggle_settings = ctx.link().callback(|_| Msg::ToggleSettings);
+        let on_search = ctx.link().callback(|e: InputEvent| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            Msg::Search(input.value())
+        });
+        let on_filter_users = ctx.link().callback(|e: InputEvent| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            Msg::FilterUsers(input.value())
+        });
+        // Filter before truncating so the filter reaches every user, not
+        // just the ones on screen.
+        let user_filter = self.user_filter.trim().to_lowercase();
+        let has_offline = self.users.iter().any(|u| !u.online);
+        let mut filtered_users: Vec<&UserProfile> = self
+            .users
+            .iter()
+            .filter(|u| u.online || self.settings.show_offline)
+            .filter(|u| u.name.to_lowercase().contains(&user_filter))
+            .collect();
+        filtered_users.sort_by_key(|u| !u.online);
+        let online_users = self.users.iter().filter(|u| u.online).count();
+        let hidden_users = filtered_users.len().saturating_sub(self.sidebar_user_limit);
+        let search_matches = self.search_index.query(&self.search_query);
+        let room_empty = !self
+            .messages
+            .iter()
+            .any(|m| m.room() == self.current_room && m.kind == MessageKind::User && m.thread_id.is_none());
+        let visible = |i: &usize, m: &MessageData| {
+            m.room() == self.current_room
+                && m.thread_id.is_none()
+                && !self.blocked.contains(&m.from)
+                && search_matches.as_ref().is_none_or(|matches| matches.contains(i))
+        };
+        let first_unread = self.unread_marker.and_then(|marker| {
+            self.messages
+                .iter()
+                .enumerate()
+                .skip(marker)
+                .find(|(i, m)| visible(i, m))
+                .map(|(i, _)| i)
+        });
+        let me = self.user.username.borrow().clone();
+        let last_own = self
+            .messages
+            .iter()
+            .rposition(|m| m.room() == self.current_room && m.from == me);
+        // Each gap is drawn before the first visible message after it, or at
+        // the end if nothing has arrived since.
+        let mut gap_before = HashSet::new();
+        let mut trailing_gap = false;
+        for &gap in &self.gaps {
+            match self
+                .messages
+                .iter()
+                .enumerate()
+                .skip(gap)
+                .find(|(i, m)| visible(i, m))
+            {
+                Some((i, _)) => {
+                    gap_before.insert(i);
+                }
+                None => trailing_gap = true,
+            }
+        }
+        let gap_divider = |key: String| {
+            html! {
+                <div {key} role="separator" class="flex items-center gap-2 text-xs italic text-[var(--chat-muted)]">
+                    <hr class="grow border-[var(--chat-border)]" />
+                    {"reconnected, some messages may be missing"}
+                    <hr class="grow border-[var(--chat-border)]" />
+                </div>
+            }
+        };
+        let submit_new_room = ctx.link().callback(|e: FocusEvent| {
+            e.prevent_default();
+            Msg::SubmitNewRoom
+        });
+        let submit_rename = ctx.link().callback(|e: FocusEvent| {
+            e.prevent_default();
+            Msg::SubmitRename
+        });
+        let on_files = ctx.link().batch_callback(|e: Event| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            let files = input.files().map(|list| {
+                (0..list.length())
+                    .filter_map(|i| list.get(i))
+                    .map(File::from)
+                    .collect()
+            });
+            input.set_value("");
+            files.map(Msg::FileSelected)
+        });
+        // Only images are taken over; anything else pastes as usual.
+        let on_paste = ctx.link().batch_callback(|e: Event| {
+            let images = pasted_images(&e);
+            if images.is_empty() {
+                return None;
+            }
+            e.prevent_default();
+            Some(Msg::FileSelected(images))
+        });
+
+        if self.all_failed() {
+            return html! {
+                <div class="flex w-screen h-screen items-center justify-center gothic" style={self.theme(ctx).style()}>
+                    <div class="flex flex-col items-center p-8 rounded-lg border border-[var(--chat-border)] bg-[var(--chat-surface)] text-center">
+                        <div class="text-xl mb-2">{"☠ The spirits are silent"}</div>
+                        <div class="mb-4 text-sm text-[var(--chat-muted)]">{"Could not connect to the chat server."}</div>
+                        <button onclick={ctx.link().callback(|_| Msg::RetryConnection)} class="px-6 py-2 rounded-full bg-[var(--chat-accent)] text-white">{"Retry"}</button>
+                    </div>
+                </div>
+            };
+        }
+
+        html! {
+            <div class={classes!("flex", "w-screen", "gothic", self.settings.plain_text.then_some("plain"), self.settings.reduce_motion.then_some("reduce-motion"))} style={self.theme(ctx).style()} onclick={ctx.link().batch_callback(|_| vec![Msg::Interacted, Msg::CloseMessageMenu])} onkeydown={ctx.link().callback(|_| Msg::Interacted)}>
+                <div class={classes!("flex-none", "w-56", "h-screen", "bg-[var(--chat-panel)]", "border-r", "border-[var(--chat-border)]", self.focus_mode.then_some("hidden"))}>
+                    <div class="text-xl p-3 border-b border-[var(--chat-border)]">{"🕯 Rooms"}</div>
+                    <div class="py-2">
+                        {
+                            self.rooms.iter().map(|room| {
+                                let active = *room == self.current_room;
+                                let onclick = {
+                                    let room = room.clone();
+                                    ctx.link().callback(move |_| Msg::SwitchRoom(room.clone()))
+                                };
+                                let cycle_notify = {
+                                    let room = room.clone();
+                                    ctx.link().callback(move |_| Msg::CycleRoomNotify(room.clone()))
+                                };
+                                let (icon, label) = match self.settings.notify_level(room) {
+                                    NotifyLevel::All => ("🔔", "Notify for all messages"),
+                                    NotifyLevel::Mentions => ("@", "Notify for mentions only"),
+                                    NotifyLevel::None => ("🔕", "Muted"),
+                                };
+                                html! {
+                                    <div class="flex items-center pr-2">
+                                        <button {onclick} class={classes!("grow", "text-left", "px-4", "py-1", "text-sm", "border-none", if active { "text-[var(--chat-highlight)]" } else { "bg-transparent" })}>
+                                            {format!("# {}", room)}
+                                        </button>
+                                        <button onclick={cycle_notify} title={label} aria-label={format!("{} in #{}", label, room)} class="px-1 text-xs bg-transparent border-none">{icon}</button>
+                                    </div>
+                                }
+                            }).collect::<Html>()
+                        }
+                        <form onsubmit={submit_new_room} class="px-3 pt-1">
+                            <input ref={self.new_room_input.clone()} placeholder="Join a room..." class="w-full py-1 px-2 text-xs rounded bg-[var(--chat-input)] outline-none" />
+                        </form>
+                    </div>
+                    <div class="text-xl p-3 border-y border-[var(--chat-border)]">{format!("☠ Users ({})", online_users)}</div>
+                    <div class="px-3 pt-2">
+                        <input oninput={on_filter_users} value={self.user_filter.clone()} placeholder="Find someone..." aria-label="Filter users" class="w-full py-1 px-2 text-xs rounded bg-[var(--chat-input)] outline-none" />
+                        {
+                            if has_offline {
+                                html! {
+                                    <label class="flex items-center gap-2 mt-2 text-xs text-[var(--chat-muted)] cursor-pointer">
+                                        <input type="checkbox" checked={self.settings.show_offline} onchange={ctx.link().callback(|_| Msg::ToggleShowOffline)} />
+                                        {"Show offline"}
+                                    </label>
+                                }
+                            } else {
+                                html! {}
+                            }
+                        }
+                    </div>
+                    {
+                        if self.loaded_users {
+                            html! {}
+                        } else {
+                            (0..3).map(|_| render_user_skeleton()).collect::<Html>()
+                        }
+                    }
+                    {
+                        filtered_users.iter().take(self.sidebar_user_limit).map(|u| {
+                            let is_blocked = self.blocked.contains(&u.name);
+                            let toggle_block = {
+                                let name = u.name.clone();
+                                ctx.link().callback(move |_| Msg::ToggleBlock(name.clone()))
+                            };
+                            html!{
+                                <div key={format!("{}@{}", u.name, u.origin)} class={classes!("flex", "m-3", "bg-[var(--chat-surface)]", "rounded-lg", "p-2", "border", "border-[var(--chat-border)]", "shadow-inner", is_blocked.then_some("opacity-40 grayscale"), (!u.online && !is_blocked).then_some("opacity-60"))}>
+                                    <div class="relative group flex-none">
+                                        <img class={classes!("w-12", "h-12", "avatar-frame", self.settings.avatar_shape.class())} src={u.avatar.clone()} onerror={avatar_fallback(u)} alt="avatar"/>
+                                        <div class="hidden group-hover:block absolute left-14 top-0 z-10 w-48 p-3 bg-[var(--chat-panel)] border border-[var(--chat-border)] rounded-lg shadow-lg text-xs">
+                                            <div class="text-sm text-[var(--chat-highlight)]">{&u.name}{render_role_badge(u.role)}</div>
+                                            {
+                                                if u.online {
+                                                    html! { <div class="text-green-500">{"● Online"}</div> }
+                                                } else {
+                                                    html! { <div class="text-[var(--chat-muted)]">{"○ Offline"}</div> }
+                                                }
+                                            }
+                                            {
+                                                if let Some(joined_at) = u.joined_at {
+                                                    html! { <div class="text-[var(--chat-muted)]">{format!("Joined at {}", format_time(joined_at, self.settings.clock))}</div> }
+                                                } else {
+                                                    html! {}
+                                                }
+                                            }
+                                        </div>
+                                    </div>
+                                    <div class="flex-grow p-3 text-sm">
+                                        <div>{&u.name}{render_role_badge(u.role)}</div>
+                                        <div class="text-xs text-[var(--chat-muted)]">
+                                            {
+                                                match (self.activity.get(&u.name), u.last_seen) {
+                                                    _ if is_blocked => "Blocked".to_string(),
+                                                    (Some((kind, since, _)), _) if still_active(&self.config, *kind, *since, now) => {
+                                                        kind.label().to_string()
+                                                    }
+                                                    (_, Some(seen)) if !u.online => {
+                                                        format!("last seen {}", format_ago(now - seen))
+                                                    }
+                                                    _ if !u.online => "Offline".to_string(),
+                                                    _ => "Summoned...".to_string(),
+                                                }
+                                            }
+                                        </div>
+                                    </div>
+                                    {
+                                        if u.name == *self.user.username.borrow() {
+                                            html! {}
+                                        } else {
+                                            html! {
+                                                <button onclick={toggle_block} title={if is_blocked { "Unblock" } else { "Block" }} class="self-center px-1 text-xs rounded bg-transparent border-none">
+                                                    {if is_blocked { "↺" } else { "🚫" }}
+                                                </button>
+                                            }
+                                        }
+                                    }
+                                </div>
+                            }
+                        }).collect::<Html>()
+                    }
+                    {
+                        if hidden_users > 0 {
+                            html! {
+                                <button onclick={ctx.link().callback(|_| Msg::ShowMoreUsers)} class="block w-full px-4 py-2 text-sm text-left text-[var(--chat-muted)] bg-transparent border-none">
+                                    {format!("+{} more", hidden_users)}
+                                </button>
+                            }
+                        } else {
+                            html! {}
+                        }
+                    }
+                    <button onclick={ctx.link().callback(|_| Msg::ToggleStats)} aria-expanded={self.show_stats.to_string()} class="block w-full text-left text-xl p-3 border-0 border-y border-[var(--chat-border)] bg-transparent">
+                        {if self.show_stats { "📊 Activity ▾" } else { "📊 Activity ▸" }}
+                    </button>
+                    {
+                        if self.show_stats {
+                            let mut counts: Vec<(&str, usize)> = vec![];
+                            for m in self.messages.iter().filter(|m| m.kind == MessageKind::User && m.time.is_none_or(|t| t >= self.session_started)) {
+                                match counts.iter_mut().find(|(name, _)| *name == m.from) {
+                                    Some((_, count)) => *count += 1 + m.repeats as usize,
+                                    None => counts.push((&m.from, 1 + m.repeats as usize)),
+                                }
+                            }
+                            match self.stats_sort {
+                                StatsSort::Count => counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0))),
+                                StatsSort::Name => counts.sort_by_key(|(name, _)| name.to_lowercase()),
+                            }
+                            let sort_button = |sort: StatsSort, label: &'static str| html! {
+                                <button onclick={ctx.link().callback(move |_| Msg::SortStats(sort))} aria-pressed={(self.stats_sort == sort).to_string()} class={classes!("px-2", "text-xs", "rounded", "border-none", (self.stats_sort != sort).then_some("bg-transparent"))}>{label}</button>
+                            };
+                            html! {
+                                <div class="px-3 py-2 text-sm">
+                                    <div class="flex gap-1 mb-2 text-[var(--chat-muted)]">
+                                        {"Sort:"}{sort_button(StatsSort::Count, "Messages")}{sort_button(StatsSort::Name, "Name")}
+                                    </div>
+                                    {
+                                        if counts.is_empty() {
+                                            html! { <div class="text-xs text-[var(--chat-muted)]">{"No messages yet this session."}</div> }
+                                        } else {
+                                            counts.into_iter().map(|(name, count)| html! {
+                                                <div key={name} class="flex justify-between py-0.5">
+                                                    <span class="truncate">{name}</span>
+                                                    <span class="text-[var(--chat-muted)]">{count}</span>
+                                                </div>
+                                            }).collect::<Html>()
+                                        }
+                                    }
+                                </div>
+                            }
+                        } else {
+                            html! {}
+                        }
+                    }
+                </div>
+                <div class="grow h-screen flex flex-col bg-[var(--chat-bg)] relative">
+                    {
+                        if self.focus_mode {
+                            html! {
+                                <button onclick={ctx.link().callback(|_| Msg::ToggleFocusMode)} title="Leave focus mode (Esc)" class="absolute top-2 right-2 z-10 px-2 text-xs rounded border border-[var(--chat-border)] bg-[var(--chat-panel)] text-[var(--chat-muted)]">{"Exit focus"}</button>
+                            }
+                        } else {
+                            html! {}
+                        }
+                    }
+                    <div class={classes!("w-full", "h-14", "border-b-2", "border-[var(--chat-border)]", "flex", "items-center", "justify-between", self.focus_mode.then_some("hidden"))}>
+                        <div class="text-xl p-3">{format!("💬 SpellCast Chat · #{}", self.current_room)}</div>
+                        {
+                            if self.renaming {
+                                html! {
+                                    <form onsubmit={submit_rename} class="flex items-center gap-2 text-xs">
+                                        <input ref={self.rename_input.clone()} value={self.user.username.borrow().clone()} class="py-1 px-2 rounded bg-[var(--chat-input)] outline-none" />
+                                        <button type="submit" class="px-2 py-1 rounded">{"Save"}</button>
+                                        <button type="button" onclick={toggle_rename} class="px-2 py-1 rounded">{"Cancel"}</button>
+                                        {
+                                            if let Some(error) = &self.rename_error {
+                                                html! { <span class="text-[var(--chat-highlight)]">{error}</span> }
+                                            } else {
+                                                html! {}
+                                            }
+                                        }
+                                    </form>
+                                }
+                            } else {
+                                html! {
+                                    <button onclick={toggle_rename} title="Change display name" class="px-2 text-xs rounded">
+                                        {format!("✎ {}", self.user.username.borrow())}
+                                    </button>
+                                }
+                            }
+                        }
+                        <input oninput={on_search} type="search" placeholder="Search..." class="ml-auto mr-2 py-1 px-3 w-40 text-xs rounded-full bg-[var(--chat-input)] outline-none" />
+                        {
+                            if clipboard {
+                                html! { <button onclick={ctx.link().callback(|_| Msg::CopyInvite)} title="Copy an invite link to this room" aria-label="Copy invite link" class="px-2 text-lg rounded bg-transparent border-none">{"🔗"}</button> }
+                            } else {
+                                html! {}
+                            }
+                        }
+                        <button onclick={ctx.link().callback(|_| Msg::ExportHistory)} title="Export this room's history" aria-label="Export history" class="px-2 text-lg rounded bg-transparent border-none">{"⇩"}</button>
+                        <button onclick={ctx.link().callback(|_| Msg::ToggleFocusMode)} title="Focus mode (Ctrl+Shift+F)" aria-label="Focus mode" class="px-2 text-lg rounded bg-transparent border-none">{"◱"}</button>
+                        {
+                            if self.config.debug_traffic {
+                                html! { <button onclick={ctx.link().callback(|_| Msg::ToggleTraffic)} title="Raw WebSocket traffic" aria-label="Toggle traffic panel" aria-pressed={self.show_traffic.to_string()} class="px-2 text-lg rounded bg-transparent border-none">{"🐞"}</button> }
+                            } else {
+                                html! {}
+                            }
+                        }
+                        <button onclick={toggle_settings.clone()} title="Settings" aria-label="Settings" class="px-2 text-lg rounded bg-transparent border-none">{"⚙"}</button>
+                        {
+                            if let Some(latency) = self.latency_ms {
+                                html! { <div class={classes!("px-4", "text-xs", latency_class(latency))} title="Round-trip latency">{format!("{} ms", latency)}</div> }
+                            } else {
+                                html! {}
+                            }
+                        }
+                    </div>
+                    {
+                        if !self.online {
+                            html! {
+                                <div role="status" class="px-4 py-1 text-xs text-center text-[var(--chat-text)] bg-[var(--chat-panel)] border-b border-[var(--chat-border)]">
+                                    {"You are offline. We'll reconnect when your network is back."}
+                                </div>
+                            }
+                        } else {
+                            html! {}
+                        }
+                    }
+                    {
+                        self.connections.iter().zip(&self.wss).filter(|_| self.online).map(|(state, wss)| {
+                            let server = if self.wss.len() > 1 { format!(" to {}", wss.url) } else { String::new() };
+                            match state {
+                                ConnectionState::Reconnecting { attempt, .. } => {
+                                    let index = self.wss.iter().position(|w| w.url == wss.url).unwrap_or(0);
+                                    let seconds = self.reconnect_at[index]
+                                        .map(|at| ((at - js_sys::Date::now()) / 1_000.0).ceil().max(0.0) as u32)
+                                        .unwrap_or(0);
+                                    let status = if seconds > 0 {
+                                        format!("Connection{} lost. Reconnecting in {}s (attempt {})… ", server, seconds, attempt)
+                                    } else {
+                                        format!("Connection{} lost. Reconnecting (attempt {})… ", server, attempt)
+                                    };
+                                    html! {
+                                        <div role="status" class="px-4 py-1 text-xs text-center text-white bg-[var(--chat-accent)]">
+                                            {status}
+                                            <button onclick={ctx.link().callback(move |_| Msg::ReconnectNow(index))} class="underline bg-transparent border-none">{"Reconnect now"}</button>
+                                        </div>
+                                    }
+                                }
+                                ConnectionState::Failed => html! {
+                                    <div role="status" class="px-4 py-1 text-xs text-center text-white bg-[var(--chat-accent)]">
+                                        {format!("Could not connect{}. ", server)}
+                                        <button onclick={ctx.link().callback(|_| Msg::RetryConnection)} class="underline bg-transparent border-none">{"Retry"}</button>
+                                    </div>
+                                },
+                                _ => html! {},
+                            }
+                        }).collect::<Html>()
+                    }
+                    {
+                        self.announcements.iter().map(|(id, text)| {
+                            let dismiss = {
+                                let id = id.clone();
+                                ctx.link().callback(move |_| Msg::DismissAnnouncement(id.clone()))
+                            };
+                            html! {
+                                <div key={id.clone()} role="alert" class="flex items-center gap-2 px-4 py-2 text-sm border-b border-[var(--chat-border)] bg-[var(--chat-panel)] text-[var(--chat-text)]">
+                                    <span class="text-[var(--chat-accent)]">{"📢"}</span>
+                                    <span class="grow">{text.clone()}</span>
+                                    <button onclick={dismiss} aria-label="Dismiss announcement" class="px-1 bg-transparent border-none">{"×"}</button>
+                                </div>
+                            }
+                        }).collect::<Html>()
+                    }
+                    <div ref={self.message_list.clone()} class={classes!("grow", "overflow-auto", "p-4", if self.settings.compact { "space-y-1" } else { "space-y-3" })}>
+                        {
+                            if self.older_observer.is_some() && !self.history_exhausted.contains(&self.current_room) {
+                                html! {
+                                    <div ref={self.older_sentinel.clone()} class="py-1 text-center text-xs text-[var(--chat-muted)]">
+                                        {if self.loading_older { "Loading earlier messages…" } else { "" }}
+                                    </div>
+                                }
+                            } else {
+                                html! {}
+                            }
+                        }
+                        {
+                            if !room_empty {
+                                html! {}
+                            } else if self.loaded_users {
+                                if search_matches.is_some() {
+                                    html! {}
+                                } else {
+                                    html! { <div class="py-16 text-center text-sm text-[var(--chat-muted)]">{&self.config.empty_room_text}</div> }
+                                }
+                            } else {
+                                ["w-48", "w-72", "w-32"].into_iter().map(render_message_skeleton).collect::<Html>()
+                            }
+                        }
+                        {
+                            self.messages.iter().enumerate().filter(|(i, m)| visible(i, m)).flat_map(|(i, m)| {
+                                let divider = (Some(i) == first_unread).then(|| html! {
+                                    <div key="unread-divider" onclick={ctx.link().callback(|_| Msg::DismissUnread)} title="Mark as read" class="flex items-center gap-2 text-xs font-semibold uppercase text-[var(--chat-accent)] cursor-pointer">
+                                        <hr class="grow border-[var(--chat-accent)]" />
+                                        {"New"}
+                                        <hr class="grow border-[var(--chat-accent)]" />
+                                    </div>
+                                });
+                                let gap = gap_before.contains(&i).then(|| gap_divider(format!("gap-{}", i)));
+                                if m.kind == MessageKind::System {
+                                    let line = html! {
+                                        <div key={m.id.clone()} role="status" title={m.detail.clone()} class={self.config.system_message_class.clone()}>{&m.message}</div>
+                                    };
+                                    return gap.into_iter().chain(divider).chain(std::iter::once(line));
+                                }
+                                // Restored history can mention people who are no longer online,
+                                // and messages can beat the first users list. Either way the
+                                // bubble shows now with a default profile instead of being held
+                                // back, and picks up the real one once the list arrives.
+                                let fallback;
+                                let user = match sender_profile(&self.users, &self.profiles, m) {
+                                    Some(user) => user,
+                                    None => {
+                                        fallback = UserProfile::new(&m.from, &self.config);
+                                        &fallback
+                                    }
+                                };
+                                let block_sender = {
+                                    let name = m.from.clone();
+                                    ctx.link().callback(move |_| Msg::ToggleBlock(name.clone()))
+                                };
+                                let reply = {
+                                    let id = m.id.clone();
+                                    ctx.link().callback(move |_| Msg::StartReply(id.clone()))
+                                };
+                                let open_thread = {
+                                    let id = m.id.clone();
+                                    ctx.link().callback(move |_| Msg::OpenThread(id.clone()))
+                                };
+                                let thread_replies = self
+                                    .messages
+                                    .iter()
+                                    .filter(|r| r.thread_id.as_ref() == Some(&m.id) && !self.blocked.contains(&r.from))
+                                    .count();
+                                let quoted = m
+                                    .reply_to
+                                    .as_ref()
+                                    .and_then(|id| self.messages.iter().find(|o| &o.id == id));
+                                let highlighted = self.highlighted.as_ref() == Some(&m.id);
+                                let limit = ctx.props().collapse_after_chars;
+                                let long = !m.message.starts_with("data:") && m.message.chars().count() > limit;
+                                let expanded = self.expanded.contains(&m.id);
+                                let body = if long && !expanded {
+                                    let mut body: String = m.message.chars().take(limit).collect();
+                                    body.push('…');
+                                    body
+                                } else {
+                                    m.message.clone()
+                                };
+                                let toggle = {
+                                    let id = m.id.clone();
+                                    ctx.link().callback(move |_| Msg::ToggleExpanded(id.clone()))
+                                };
+                                let content = html! {
+                                    <>
+                                        {self.render_content(ctx, m, &body)}
+                                        {
+                                            if long {
+                                                html! { <button onclick={toggle} aria-expanded={expanded.to_string()} class="p-0 text-xs text-[var(--chat-highlight)] bg-transparent border-none">{if expanded { "Show less" } else { "Show more" }}</button> }
+                                            } else {
+                                                html! {}
+                                            }
+                                        }
+                                    </>
+                                };
+                                let onmouseenter = {
+                                    let id = m.id.clone();
+                                    ctx.link().callback(move |_| Msg::HoverMessage(Some(id.clone())))
+                                };
+                                let onmouseleave = ctx.link().callback(|_| Msg::HoverMessage(None));
+                                // Keyboard focus picks the target for quick reactions, like hovering.
+                                let onfocusin = {
+                                    let id = m.id.clone();
+                                    ctx.link().callback(move |_: FocusEvent| Msg::HoverMessage(Some(id.clone())))
+                                };
+                                // The menu only offers copying, so without a clipboard the
+                                // browser's own menu is left alone.
+                                let oncontextmenu = clipboard.then(|| {
+                                    let id = m.id.clone();
+                                    ctx.link().callback(move |e: MouseEvent| {
+                                        e.prevent_default();
+                                        Msg::OpenMessageMenu(id.clone(), e.client_x(), e.client_y())
+                                    })
+                                });
+                                // Own messages sit on the right, with their timestamp on the
+                                // right too; everyone else's stay on the left.
+                                let own = m.from == me;
+                                // Shimmers until the server acks it.
+                                let sending = own && self.delivery.get(&m.id) == Some(&Delivery::Sending);
+                                let timestamp = match m.time {
+                                    Some(time) if self.settings.timestamps_on_hover => html! {
+                                        <span title={format_datetime(time, self.settings.clock)} class={classes!("hidden", "group-hover/bubble:block", "absolute", "-top-2", if own { "right-2" } else { "left-2" }, "px-1", "rounded", "bg-[var(--chat-panel)]", "text-[10px]", "font-normal", "text-[var(--chat-muted)]")}>{format_time(time, self.settings.clock)}</span>
+                                    },
+                                    Some(time) => html! {
+                                        <span title={format_datetime(time, self.settings.clock)} class="ml-2 text-[10px] font-normal text-[var(--chat-muted)]">{format_time(time, self.settings.clock)}</span>
+                                    },
+                                    None => html! {},
+                                };
+                                let bubble = html!{
+                                    <div key={m.id.clone()} tabindex="0" {onfocusin} {onmouseenter} {onmouseleave} {oncontextmenu} ref={self.message_refs.get(&m.id).cloned().unwrap_or_default()} class={classes!("group/bubble", "flex", own.then_some("ml-auto flex-row-reverse"), "bg-[var(--chat-surface)]", if self.settings.compact { "p-1.5" } else { "p-3" }, "rounded-lg", "border", "border-[var(--chat-border)]", "shadow-sm", "w-fit", ctx.props().bubble_max_width.clone(), "transition", "relative", highlighted.then_some("ring-2 ring-[var(--chat-highlight)]"), sending.then_some("sending"), self.away_ids.contains(&m.id).then_some("border-l-4 border-l-[var(--chat-accent)]"))}>
+                                        {
+                                            if self.settings.show_avatars && !self.settings.plain_text {
+                                                html! { <img class={classes!("w-8", "h-8", "avatar-frame", if own { "ml-3" } else { "mr-3" }, self.settings.avatar_shape.class())} src={user.avatar.clone()} onerror={avatar_fallback(user)} /> }
+                                            } else {
+                                                html! {}
+                                            }
+                                        }
+                                        <div>
+                                            <div class={classes!("text-sm", "font-semibold", "text-[var(--chat-highlight)]", own.then_some("text-right"))}>
+                                                {m.from.clone()}{render_role_badge(user.role)}
+                                                {
+                                                    match &m.origin {
+                                                        Some(origin) if self.wss.len() > 1 => html! {
+                                                            <span class="ml-1 text-[10px] font-normal text-[var(--chat-muted)]">{format!("@{}", origin)}</span>
+                                                        },
+                                                        _ => html! {},
+                                                    }
+                                                }
+                                                { if own { html! {} } else { timestamp.clone() } }
+                                                <button onclick={reply} title="Reply" class="hidden group-hover/bubble:inline ml-2 text-xs bg-transparent border-none">{"↩"}</button>
+                                                <button onclick={open_thread.clone()} title="Reply in thread" class="hidden group-hover/bubble:inline ml-2 text-xs bg-transparent border-none">{"🧵"}</button>
+                                                {
+                                                    if own {
+                                                        if m.message.starts_with("data:") {
+                                                            html! {}
+                                                        } else {
+                                                            let edit = {
+                                                                let id = m.id.clone();
+                                                                ctx.link().callback(move |_| Msg::StartEdit(id.clone()))
+                                                            };
+                                                            html! { <button onclick={edit} title="Edit" class="hidden group-hover/bubble:inline ml-2 text-xs bg-transparent border-none">{"✏"}</button> }
+                                                        }
+                                                    } else {
+                                                        html! { <button onclick={block_sender} title={format!("Block {}", m.from)} class="hidden group-hover/bubble:inline ml-2 text-xs bg-transparent border-none">{"🚫"}</button> }
+                                                    }
+                                                }
+                                                { if own { timestamp } else { html! {} } }
+                                            </div>
+                                            {
+                                                if let Some(original) = quoted {
+                                                    let quote_line = |target: &MessageData, text: &str| {
+                                                        let scroll = {
+                                                            let id = target.id.clone();
+                                                            ctx.link().callback(move |_| Msg::ScrollToMessage(id.clone()))
+                                                        };
+                                                        html! {
+                                                            <button onclick={scroll} title="Jump to message" class="block w-full text-left my-1 pl-2 py-0.5 border-0 border-l-2 border-[var(--chat-border)] bg-transparent text-xs text-[var(--chat-muted)]">
+                                                                {format!("{}: {}", target.from, snippet(text))}
+                                                            </button>
+                                                        }
+                                                    };
+                                                    // Only the message being answered shows by default; the
+                                                    // rest of the chain sits behind "Show context".
+                                                    let ancestors = self.reply_ancestors(original);
+                                                    let context_open = self.expanded_context.contains(&m.id);
+                                                    let toggle_context = {
+                                                        let id = m.id.clone();
+                                                        ctx.link().callback(move |_| Msg::ToggleContext(id.clone()))
+                                                    };
+                                                    html! {
+                                                        <>
+                                                            {
+                                                                if ancestors.is_empty() {
+                                                                    html! {}
+                                                                } else {
+                                                                    html! {
+                                                                        <button onclick={toggle_context} aria-expanded={context_open.to_string()} class="p-0 text-xs text-[var(--chat-highlight)] bg-transparent border-none">
+                                                                            { if context_open { "Hide context".to_string() } else { format!("Show context ({})", ancestors.len()) } }
+                                                                        </button>
+                                                                    }
+                                                                }
+                                                            }
+                                                            {
+                                                                if context_open {
+                                                                    ancestors.iter().map(|a| quote_line(a, &a.message)).collect::<Html>()
+                                                                } else {
+                                                                    html! {}
+                                                                }
+                                                            }
+                                                            { quote_line(original, m.quote.as_deref().unwrap_or(&original.message)) }
+                                                        </>
+                                                    }
+                                                } else {
+                                                    html! {}
+                                                }
+                                            }
+                                            {
+                                                if self.editing.as_ref() == Some(&m.id) {
+                                                    let submit_edit = ctx.link().callback(|e: FocusEvent| {
+                                                        e.prevent_default();
+                                                        Msg::SubmitEdit
+                                                    });
+                                                    let onkeydown = ctx.link().batch_callback(|e: KeyboardEvent| {
+                                                        (e.key() == "Escape").then_some(Msg::CancelEdit)
+                                                    });
+                                                    html! {
+                                                        <form onsubmit={submit_edit} class="flex items-center gap-2 mt-1 text-xs">
+                                                            <input ref={self.edit_input.clone()} {onkeydown} value={m.message.clone()} aria-label="Edit message" class="py-1 px-2 text-sm rounded bg-[var(--chat-input)] outline-none" />
+                                                            <button type="submit" class="px-2 py-1 rounded">{"Save"}</button>
+                                                            <button type="button" onclick={ctx.link().callback(|_| Msg::CancelEdit)} class="px-2 py-1 rounded">{"Cancel"}</button>
+                                                        </form>
+                                                    }
+                                                } else {
+                                                    content
+                                                }
+                                            }
+                                            {
+                                                if self.remote_editing.contains_key(&m.id) {
+                                                    html! { <div class="text-[10px] italic text-[var(--chat-muted)] animate-pulse">{"(editing…)"}</div> }
+                                                } else if m.edited {
+                                                    html! { <div class="text-[10px] text-[var(--chat-muted)]">{"(edited)"}</div> }
+                                                } else {
+                                                    html! {}
+                                                }
+                                            }
+                                            {
+                                                if m.repeats > 0 {
+                                                    html! { <div class="text-xs italic text-[var(--chat-muted)]">{format!("repeated {} times", m.repeats + 1)}</div> }
+                                                } else {
+                                                    html! {}
+                                                }
+                                            }
+                                            {
+                                                {
+                                                    let status = self.delivery.get(&m.id).copied();
+                                                    let color = if status == Some(Delivery::Failed) { "text-red-500" } else { "text-[var(--chat-muted)]" };
+                                                    match delivery_note(status, self.recipients.get(&m.id).copied()) {
+                                                        Some(note) => html! { <div class={classes!("text-[10px]", color)}>{note}</div> },
+                                                        None => html! {},
+                                                    }
+                                                }
+                                            }
+                                            {
+                                                match last_own {
+                                                    Some(own) if own == i => {
+                                                        let seen = self.seen_by(i);
+                                                        if seen.is_empty() {
+                                                            html! {}
+                                                        } else {
+                                                            let tooltip = seen
+                                                                .iter()
+                                                                .map(|(user, time)| format!("{} at {}", user, format_datetime(*time, self.settings.clock)))
+                                                                .collect::<Vec<_>>()
+                                                                .join("\n");
+                                                            html! { <div title={tooltip} class="text-[10px] text-[var(--chat-muted)] cursor-default">{format!("Seen by {}", seen.len())}</div> }
+                                                        }
+                                                    }
+                                                    _ => html! {},
+                                                }
+                                            }
+                                            {
+                                                match thread_replies {
+                                                    0 => html! {},
+                                                    n => html! {
+                                                        <button onclick={open_thread} class="p-0 mt-1 text-xs font-semibold text-[var(--chat-highlight)] bg-transparent border-none">
+                                                            {if n == 1 { "1 reply".to_string() } else { format!("{} replies", n) }}
+                                                        </button>
+                                                    },
+                                                }
+                                            }
+                                            {render_reactions(ctx.link(), m, &me)}
+                                        </div>
+                                    </div>
+                                };
+                                gap.into_iter().chain(divider).chain(std::iter::once(bubble))
+                            }).chain(trailing_gap.then(|| gap_divider("gap-end".into()))).collect::<Html>()
+                        }
+                    </div>
+                    {
+                        match &self.attachment_error {
+                            Some(error) => html! {
+                                <div role="alert" class="px-3 pt-2 text-xs bg-[var(--chat-panel)] text-[var(--chat-highlight)]">{error}</div>
+                            },
+                            None => html! {},
+                        }
+                    }
+                    {
+                        if self.pending_attachments.is_empty() {
+                            html! {}
+                        } else {
+                            html! {
+                                <div class="flex gap-2 px-3 pt-3 border-t border-[var(--chat-border)] bg-[var(--chat-panel)] overflow-x-auto">
+                                    {
+                                        self.pending_attachments.iter().enumerate().map(|(i, a)| {
+                                            let remove = ctx.link().callback(move |_| Msg::RemoveAttachment(i));
+                                            html! {
+                                                <div class="relative flex-none w-16 h-16 rounded border border-[var(--chat-border)] bg-[var(--chat-input)] overflow-hidden" title={format!("{} ({})", a.name, format_size(a.size))}>
+                                                    {
+                                                        if a.mime_type.starts_with("image/") {
+                                                            html! { <img class="w-full h-full object-cover" src={a.data_url.clone()} alt={a.name.clone()} /> }
+                                                        } else {
+                                                            html! { <div class="p-1 text-[10px] text-[var(--chat-text)] break-all">{&a.name}</div> }
+                                                        }
+                                                    }
+                                                    <button onclick={remove} aria-label={format!("Remove {}", a.name)} class="absolute top-0 right-0 w-5 h-5 text-xs leading-none rounded-full bg-[var(--chat-accent)] text-white">{"×"}</button>
+                                                </div>
+                                            }
+                                        }).collect::<Html>()
+                                    }
+                                </div>
+                            }
+                        }
+                    }
+                    {
+                        if self.activity.is_empty() {
+                            html! {}
+                        } else {
+                            let mut active: Vec<(&String, ActivityKind)> = self
+                                .activity
+                                .iter()
+                                .filter(|(name, _)| !self.blocked.contains(*name))
+                                .filter(|(_, (kind, since, _))| still_active(&self.config, *kind, *since, now))
+                                .map(|(name, (kind, _, _))| (name, *kind))
+                                .collect();
+                            active.sort_by_key(|(name, _)| *name);
+                            html! {
+                                <div aria-live="polite" class="px-4 py-1 text-xs italic text-[var(--chat-muted)] animate-pulse">
+                                    {
+                                        active.into_iter().map(|(name, kind)| html! {
+                                            <div>{format!("{} {}", name, kind.label())}</div>
+                                        }).collect::<Html>()
+                                    }
+                                </div>
+                            }
+                        }
+                    }
+                    {
+                        match self.replying_to.as_ref().and_then(|id| self.messages.iter().find(|m| &m.id == id)) {
+                            Some(original) => html! {
+                                <div class="flex items-center gap-2 px-3 pt-2 border-t border-[var(--chat-border)] bg-[var(--chat-panel)] text-xs text-[var(--chat-muted)]">
+                                    <span class="truncate">{format!("Replying to {}: {}", original.from, snippet(self.reply_quote.as_deref().unwrap_or(&original.message)))}</span>
+                                    <button onclick={ctx.link().callback(|_| Msg::CancelReply)} aria-label="Cancel reply" class="ml-auto px-1 bg-transparent border-none">{"×"}</button>
+                                </div>
+                            },
+                            None => html! {},
+                        }
+                    }
+                    {
+                        if self.gif_picker_open {
+                            let search_gifs = ctx.link().callback(|e: FocusEvent| {
+                                e.prevent_default();
+                                Msg::SearchGifs
+                            });
+                            html! {
+                                <div class="px-3 pt-2 border-t border-[var(--chat-border)] bg-[var(--chat-panel)]">
+                                    <form onsubmit={search_gifs} class="flex gap-2">
+                                        <input ref={self.gif_input.clone()} placeholder="Search GIFs..." aria-label="Search GIFs" class="grow py-1 px-2 text-sm rounded bg-[var(--chat-input)] outline-none" />
+                                        <button type="submit" class="px-3 text-sm rounded">{"Search"}</button>
+                                    </form>
+                                    {
+                                        if self.gif_loading {
+                                            html! { <div class="py-2 text-xs text-[var(--chat-muted)]">{"Searching…"}</div> }
+                                        } else if let Some(error) = &self.gif_error {
+                                            html! { <div class="py-2 text-xs text-[var(--chat-muted)]">{error.clone()}</div> }
+                                        } else {
+                                            html! {}
+                                        }
+                                    }
+                                    <div class="grid grid-cols-6 gap-2 py-2 max-h-48 overflow-y-auto">
+                                        {
+                                            self.gif_results.iter().map(|g| {
+                                                let onclick = {
+                                                    let url = g.url.clone();
+                                                    ctx.link().callback(move |_| Msg::SendGif(url.clone()))
+                                                };
+                                                html! {
+                                                    <button {onclick} class="p-0 bg-transparent border-none">
+                                                        <img src={g.preview.clone()} loading="lazy" class="w-full h-20 object-cover rounded" />
+                                                    </button>
+                                                }
+                                            }).collect::<Html>()
+                                        }
+                                    </div>
+                                </div>
+                            }
+                        } else {
+                            html! {}
+                        }
+                    }
+                    {
+                        if self.emoji_picker_open {
+                            let onkeydown = ctx.link().batch_callback(|e: KeyboardEvent| {
+                                let key = e.key();
+                                let handled = matches!(
+                                    key.as_str(),
+                                    "ArrowLeft" | "ArrowRight" | "ArrowUp" | "ArrowDown" | "Home" | "End" | "Enter" | "Escape"
+                                );
+                                handled.then(|| {
+                                    // Keeps Escape and the like away from the page-wide shortcuts.
+                                    e.prevent_default();
+                                    e.stop_propagation();
+                                    Msg::EmojiPickerKey(key)
+                                })
+                            });
+                            html! {
+                                <div ref={self.emoji_picker.clone()} role="listbox" aria-label="Pick an emoji" tabindex="0" aria-activedescendant={format!("emoji-{}", self.emoji_selected)} {onkeydown} class="grid grid-cols-8 gap-1 px-3 py-2 border-t border-[var(--chat-border)] bg-[var(--chat-panel)] outline-none">
+                                    {
+                                        PICKER_EMOJI.iter().enumerate().map(|(i, emoji)| {
+                                            let selected = i == self.emoji_selected;
+                                            let onclick = ctx.link().callback(move |_| Msg::InsertEmoji(emoji));
+                                            html! {
+                                                <button id={format!("emoji-{}", i)} type="button" role="option" tabindex="-1" aria-selected={selected.to_string()} {onclick} class={classes!("p-1", "text-xl", "rounded", "bg-transparent", "border-none", selected.then_some("ring-2 ring-[var(--chat-highlight)]"))}>{*emoji}</button>
+                                            }
+                                        }).collect::<Html>()
+                                    }
+                                </div>
+                            }
+                        } else {
+                            html! {}
+                        }
+                    }
+                    <div class="relative flex items-center px-3 py-4 border-t border-[var(--chat-border)] bg-[var(--chat-panel)]">
+                        {
+                            if mention_candidates.is_empty() {
+                                html! {}
+                            } else {
+                                html! {
+                                    <ul role="listbox" aria-label="Mention someone" class="absolute bottom-full left-24 z-20 mb-1 w-56 py-1 rounded-lg border border-[var(--chat-border)] bg-[var(--chat-surface)] shadow-lg">
+                                        {
+                                            mention_candidates.iter().enumerate().map(|(i, name)| {
+                                                let selected = i == self.mention_selected;
+                                                // Picking happens on mousedown, before the
+                                                // input's blur closes the list.
+                                                let onmousedown = {
+                                                    let name = name.clone();
+                                                    ctx.link().callback(move |e: MouseEvent| {
+                                                        e.prevent_default();
+                                                        Msg::PickMention(name.clone())
+                                                    })
+                                                };
+                                                html! {
+                                                    <li role="option" aria-selected={selected.to_string()} {onmousedown} class={classes!("px-3", "py-1", "text-sm", "cursor-pointer", "text-[var(--chat-text)]", selected.then_some("bg-[var(--chat-input)] text-[var(--chat-highlight)]"))}>
+                                                        {format!("@{}", name)}
+                                                    </li>
+                                                }
+                                            }).collect::<Html>()
+                                        }
+                                    </ul>
+                                }
+                            }
+                        }
+                        <label class="mr-3 cursor-pointer text-xl" title="Attach files">
+                            {"📎"}
+                            <input type="file" multiple=true accept={self.config.attachment_types.join(",")} class="hidden" onchange={on_files} />
+                        </label>
+                        <button onclick={ctx.link().callback(|_| Msg::ToggleEmojiPicker)} title="Emoji (Ctrl+E)" aria-label="Emoji" aria-expanded={self.emoji_picker_open.to_string()} class="mr-3 px-1 text-xl rounded bg-transparent border-none">{"😊"}</button>
+                        <button onclick={ctx.link().callback(|_| Msg::ToggleGifPicker)} title="Search GIFs" aria-label="Search GIFs" class="mr-3 px-2 text-xs font-bold rounded bg-transparent border border-[var(--chat-border)]">{"GIF"}</button>
+                        <textarea ref={self.chat_input.clone()} oninput={on_draft} onpaste={on_paste} onkeydown={on_composer_key} onblur={ctx.link().batch_callback(|_| vec![Msg::MentionChanged(None), Msg::SaveDraft])} rows="1" placeholder="Speak your mind..." aria-label="Message" class="w-full py-2 px-4 leading-6 bg-[var(--chat-input)] rounded-2xl resize-none overflow-y-hidden text-[var(--chat-text)] outline-none" />
+                        <button onclick={ctx.link().callback(|_| Msg::TogglePreview)} title="Preview" aria-label="Preview" aria-pressed={self.show_preview.to_string()} class={classes!("ml-3", "px-2", "text-lg", "rounded", "bg-transparent", "border-none", (!self.show_preview).then_some("opacity-50"))}>{"👁"}</button>
+                        <button onclick={submit} aria-label="Send" class="ml-3 bg-[var(--chat-accent)] hover:brightness-125 text-white p-3 rounded-full">
+                            {
+                                match &ctx.props().send_button {
+                                    Some(content) => content.clone(),
+                                    None => html! {
+                                        <svg viewBox="0 0 24 24" class="w-5 h-5 fill-white"><path d="M2.01 21L23 12 2.01 3 2 10l15 2-15 2z"/></svg>
+                                    },
+                                }
+                            }
+                        </button>
+                    </div>
+                    {
+                        match self.drafts.get(&self.current_room) {
+                            Some(draft) if self.show_preview => html! {
+                                <div aria-label="Message preview" class="px-4 pb-3 bg-[var(--chat-panel)]">
+                                    <div class="text-[10px] uppercase text-[var(--chat-muted)]">{"Preview"}</div>
+                                    <div class={classes!("p-2", "rounded-lg", "border", "border-dashed", "border-[var(--chat-border)]", "bg-[var(--chat-surface)]", ctx.props().bubble_max_width.clone())}>
+                                        {
+                                            if self.settings.plain_text {
+                                                render_plain(draft)
+                                            } else {
+                                                render_message(&self.renderers, draft)
+                                            }
+                                        }
+                                    </div>
+                                </div>
+                            },
+                            _ => html! {},
+                        }
+                    }
+                </div>
+                {
+                    match self.open_thread.as_ref().and_then(|id| self.messages.iter().find(|m| &m.id == id)) {
+                        Some(root) => {
+                            let submit_thread = ctx.link().callback(|e: FocusEvent| {
+                                e.prevent_default();
+                                Msg::SubmitThreadReply
+                            });
+                            let render_entry = |m: &MessageData| html! {
+                                <div key={m.id.clone()} class="p-2 rounded-lg border border-[var(--chat-border)] bg-[var(--chat-surface)]">
+                                    <div class="text-sm font-semibold text-[var(--chat-highlight)]">
+                                        {m.from.clone()}
+                                        {
+                                            match m.time {
+                                                Some(time) => html! {
+                                                    <span title={format_datetime(time, self.settings.clock)} class="ml-2 text-[10px] font-normal text-[var(--chat-muted)]">{format_time(time, self.settings.clock)}</span>
+                                                },
+                                                None => html! {},
+                                            }
+                                        }
+                                    </div>
+                                    {self.render_content(ctx, m, &m.message)}
+                                </div>
+                            };
+                            html! {
+                                <aside aria-label="Thread" class="flex flex-col flex-none w-80 border-l border-[var(--chat-border)] bg-[var(--chat-panel)]">
+                                    <div class="flex items-center justify-between px-3 py-3 border-b border-[var(--chat-border)]">
+                                        <span class="font-semibold text-[var(--chat-text)]">{"Thread"}</span>
+                                        <button onclick={ctx.link().callback(|_| Msg::CloseThread)} aria-label="Close thread" class="px-1 bg-transparent border-none">{"×"}</button>
+                                    </div>
+                                    <div class="grow p-3 space-y-2 overflow-auto">
+                                        {render_entry(root)}
+                                        {
+                                            self.messages
+                                                .iter()
+                                                .filter(|m| m.thread_id.as_ref() == Some(&root.id) && !self.blocked.contains(&m.from))
+                                                .map(render_entry)
+                                                .collect::<Html>()
+                                        }
+                                    </div>
+                                    <form onsubmit={submit_thread} class="flex gap-2 p-3 border-t border-[var(--chat-border)]">
+                                        <input ref={self.thread_input.clone()} placeholder="Reply in thread..." aria-label="Reply in thread" class="grow py-1 px-3 text-sm rounded-full bg-[var(--chat-input)] text-[var(--chat-text)] outline-none" />
+                                        <button type="submit" class="px-3 text-sm rounded-full bg-[var(--chat-accent)] text-white">{"Send"}</button>
+                                    </form>
+                                </aside>
+                            }
+                        }
+                        None => html! {},
+                    }
+                }
+                {
+                    if self.show_settings {
+                        html! { <SettingsPanel on_close={ctx.link().callback(|_| Msg::ToggleSettings)} /> }
+                    } else {
+                        html! {}
+                    }
+                }
+                {
+                    if self.room_switcher_open {
+                        let matches: Vec<&String> = self
+                            .rooms
+                            .iter()
+                            .filter(|r| fuzzy_match(&self.room_switcher_query, r))
+                            .collect();
+                        let first = matches.first().map(|r| r.to_string());
+                        let onsubmit = ctx.link().batch_callback(move |e: FocusEvent| {
+                            e.prevent_default();
+                            first.clone().map(Msg::SwitchRoom)
+                        });
+                        let oninput = ctx.link().callback(|e: InputEvent| {
+                            let input: HtmlInputElement = e.target_unchecked_into();
+                            Msg::FilterRoomSwitcher(input.value())
+                        });
+                        let onkeydown = ctx.link().batch_callback(|e: KeyboardEvent| {
+                            (e.key() == "Escape").then_some(Msg::ToggleRoomSwitcher)
+                        });
+                        html! {
+                            <div class="fixed inset-0 z-20 flex justify-center pt-24 bg-black/60" onclick={ctx.link().callback(|_| Msg::ToggleRoomSwitcher)}>
+                                <div role="dialog" aria-label="Switch room" onclick={Callback::from(|e: MouseEvent| e.stop_propagation())} class="w-80 h-fit p-3 rounded-lg border border-[var(--chat-border)] bg-[var(--chat-surface)]">
+                                    <form {onsubmit}>
+                                        <input ref={self.room_switcher_input.clone()} {oninput} {onkeydown} value={self.room_switcher_query.clone()} placeholder="Jump to a room..." class="w-full py-2 px-3 rounded bg-[var(--chat-input)] outline-none" />
+                                    </form>
+                                    <div class="mt-2">
+                                        {
+                                            matches.into_iter().map(|room| {
+                                                let onclick = {
+                                                    let room = room.clone();
+                                                    ctx.link().callback(move |_| Msg::SwitchRoom(room.clone()))
+                                                };
+                                                html! {
+                                                    <button {onclick} class={classes!("block", "w-full", "text-left", "px-3", "py-1", "text-sm", "border-none", if *room == self.current_room { "text-[var(--chat-highlight)]" } else { "bg-transparent" })}>
+                                                        {format!("# {}", room)}
+                                                    </button>
+                                                }
+                                            }).collect::<Html>()
+                                        }
+                                    </div>
+                                </div>
+                            </div>
+                        }
+                    } else {
+                        html! {}
+                    }
+                }
+                {
+                    if self.show_traffic {
+                        let mut frames: Vec<(&str, RawFrame)> = self
+                            .wss
+                            .iter()
+                            .flat_map(|ws| ws.traffic().into_iter().map(move |f| (ws.url.as_str(), f)))
+                            .collect();
+                        frames.sort_by(|a, b| a.1.time.total_cmp(&b.1.time));
+                        let multi = self.wss.len() > 1;
+                        html! {
+                            <div role="log" aria-label="WebSocket traffic" class="fixed bottom-4 left-4 z-30 w-[32rem] max-h-80 overflow-y-auto p-2 rounded-lg shadow-lg font-mono text-[11px] bg-[var(--chat-panel)] border border-[var(--chat-border)] text-[var(--chat-text)]">
+                                {
+                                    frames.into_iter().map(|(url, f)| html! {
+                                        <div class="break-all">
+                                            <span class={if f.outbound { "text-amber-500" } else { "text-green-500" }}>{if f.outbound { "↑ " } else { "↓ " }}</span>
+                                            <span class="text-[var(--chat-muted)]">{format_time(f.time, self.settings.clock)}{if multi { format!(" {}", url) } else { String::new() }}{" "}</span>
+                                            {f.data}
+                                        </div>
+                                    }).collect::<Html>()
+                                }
+                            </div>
+                        }
+                    } else {
+                        html! {}
+                    }
+                }
+                {
+                    match &self.message_menu {
+                        Some((id, x, y)) => {
+                            let copy = {
+                                let id = id.clone();
+                                ctx.link().callback(move |_| Msg::CopyMessageLink(id.clone()))
+                            };
+                            html! {
+                                <div role="menu" style={format!("left: {}px; top: {}px", x, y)} class="fixed z-30 py-1 rounded-lg shadow-lg bg-[var(--chat-panel)] border border-[var(--chat-border)]">
+                                    <button role="menuitem" onclick={copy} class="block w-full px-3 py-1 text-left text-sm text-[var(--chat-text)] bg-transparent border-none">{"🔗 Copy link to message"}</button>
+                                </div>
+                            }
+                        }
+                        None => html! {},
+                    }
+                }
+                <div aria-live="polite" class="fixed bottom-4 right-4 z-30 flex flex-col gap-2">
+                    {
+                        self.toasts.iter().map(|t| {
+                            let id = t.id;
+                            html! {
+                                <div key={id} role="status" onclick={ctx.link().callback(move |_| Msg::DismissToast(id))} class={classes!("px-4", "py-2", "text-sm", "rounded-lg", "shadow-lg", "cursor-pointer", "border", if t.success { "text-white bg-green-700 border-green-600" } else { "text-[var(--chat-text)] bg-[var(--chat-panel)] border-[var(--chat-border)]" })}>
+                                    {t.text.clone()}
+                                </div>
+                            }
+                        }).collect::<Html>()
+                    }
+                </div>
+            </div>
+        }
+
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(json: &str) -> MessageData {
+        serde_json::from_str(json).expect("valid message json")
+    }
+
+    #[test]
+    fn saved_history_keeps_timestamps() {
+        let saved = vec![
+            message(r#"{"id":"a","from":"ann","message":"hi","time":1700000000123.5}"#),
+            message(r#"{"id":"b","from":"bob","message":"no clock"}"#),
+        ];
+        let json = serde_json::to_string(&saved).unwrap();
+        let loaded: Vec<MessageData> = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded[0].time, Some(1700000000123.5));
+        assert_eq!(loaded[1].time, None);
+    }
+
+    #[test]
+    fn reactions_survive_a_history_round_trip() {
+        let mut original = message(r#"{"id":"a","from":"ann","message":"hi","time":1}"#);
+        original.reactions.insert("👍".into(), vec!["bob".into(), "cy".into()]);
+        let entry = serde_json::to_string(&original).unwrap();
+        let frame = serde_json::json!({ "messageType": "history", "dataArray": [entry] }).to_string();
+        let replayed = match decode_frame(&frame) {
+            Ok(Incoming::History { mut entries, .. }) => entries.remove(0).unwrap(),
+            _ => panic!("expected a history page"),
+        };
+        assert_eq!(replayed.reactions, original.reactions);
+
+        // Rehydrating a bubble that lost its reactions brings them back.
+        let mut local = message(r#"{"id":"a","from":"ann","message":"hi","time":1}"#);
+        local.merge_reactions(replayed.reactions);
+        assert_eq!(local.reactions, original.reactions);
+        // A copy without reactions leaves them alone.
+        local.merge_reactions(HashMap::new());
+        assert_eq!(local.reactions, original.reactions);
+    }
+
+    #[test]
+    fn overlapping_backfill_keeps_one_bubble_per_id() {
+        let mut messages = vec![
+            message(r#"{"id":"a","from":"ann","message":"one","time":1}"#),
+            message(r#"{"id":"b","from":"bob","message":"two"}"#),
+        ];
+        let batch = vec![
+            message(r#"{"id":"b","from":"bob","message":"two","time":2,"reactions":{"👍":["ann"]}}"#),
+            message(r#"{"id":"c","from":"cy","message":"three","time":3}"#),
+            message(r#"{"id":"c","from":"cy","message":"three","time":3}"#),
+            message(r#"{"id":"d","from":"ann","message":"four","time":4}"#),
+        ];
+        let (fresh, known) = merge_backfill(&mut messages, batch);
+        assert_eq!(known, ["b"]);
+        assert_eq!(fresh.iter().map(|m| m.id.as_str()).collect::<Vec<_>>(), ["c", "d"]);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].time, Some(2.0));
+        assert_eq!(messages[1].reactions["👍"], ["ann"]);
+
+        // Replaying the same range again adds nothing.
+        messages.extend(fresh);
+        let replay = vec![
+            message(r#"{"id":"c","from":"cy","message":"three","time":3}"#),
+            message(r#"{"id":"d","from":"ann","message":"four","time":4}"#),
+        ];
+        let (fresh, known) = merge_backfill(&mut messages, replay);
+        assert!(fresh.is_empty());
+        assert_eq!(known, ["c", "d"]);
+        assert_eq!(messages.len(), 4);
+    }
+
+    #[test]
+    fn typing_expires_after_its_own_ttl() {
+        let config = ChatConfig {
+            typing_ttl_ms: 3_000,
+            activity_ttl_ms: 5_000,
+            ..ChatConfig::default()
+        };
+        assert!(still_active(&config, ActivityKind::Typing, 10_000.0, 12_999.0));
+        assert!(!still_active(&config, ActivityKind::Typing, 10_000.0, 13_000.0));
+        // Other activity keeps the longer, general TTL.
+        assert!(still_active(&config, ActivityKind::Uploading, 10_000.0, 14_999.0));
+        assert!(!still_active(&config, ActivityKind::Uploading, 10_000.0, 15_000.0));
+    }
+
+    #[test]
+    fn twelve_hour_clock() {
+        assert_eq!(clock_time(0, 0, true), "12:00 AM");
+        assert_eq!(clock_time(9, 5, true), "9:05 AM");
+        assert_eq!(clock_time(12, 0, true), "12:00 PM");
+        assert_eq!(clock_time(13, 5, true), "1:05 PM");
+        assert_eq!(clock_time(23, 59, true), "11:59 PM");
+    }
+
+    #[test]
+    fn twenty_four_hour_clock() {
+        assert_eq!(clock_time(0, 0, false), "00:00");
+        assert_eq!(clock_time(9, 5, false), "09:05");
+        assert_eq!(clock_time(12, 0, false), "12:00");
+        assert_eq!(clock_time(13, 5, false), "13:05");
+        assert_eq!(clock_time(23, 59, false), "23:59");
+    }
+
+    #[test]
+    fn decodes_a_valid_message_frame() {
+        let raw = r#"{"messageType":"message","data":"{\"from\":\"ann\",\"message\":\"hi\",\"time\":5}"}"#;
+        match decode_frame(raw) {
+            Ok(Incoming::Message(m)) => {
+                assert_eq!(m.from, "ann");
+                assert_eq!(m.message, "hi");
+                assert_eq!(m.id, "ann-5");
+            }
+            _ => panic!("expected a message"),
+        }
+    }
+
+    #[test]
+    fn decodes_a_valid_users_frame() {
+        let raw = r#"{"messageType":"users","dataArray":["ann","bob"]}"#;
+        match decode_frame(raw) {
+            Ok(Incoming::Users { names, details }) => {
+                assert_eq!(names, ["ann", "bob"]);
+                assert!(details.is_empty());
+            }
+            _ => panic!("expected a user list"),
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        assert!(matches!(decode_frame("not json"), Err(FrameError::Malformed(_))));
+        assert!(matches!(decode_frame(r#"{"data":"hi"}"#), Err(FrameError::Malformed(_))));
+        assert!(matches!(
+            decode_frame(r#"{"messageType":"shout","data":"hi"}"#),
+            Err(FrameError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn message_frames_with_bad_data_become_unreadable() {
+        let raw = r#"{"messageType":"message","room":"dev"}"#;
+        match decode_frame(raw) {
+            Ok(Incoming::Unreadable { room, reason }) => {
+                assert_eq!(room.as_deref(), Some("dev"));
+                assert_eq!(reason, "message frame without data");
+            }
+            _ => panic!("expected an unreadable message"),
+        }
+        let raw = r#"{"messageType":"message","data":"{\"message\":\"no sender\"}"}"#;
+        assert!(matches!(decode_frame(raw), Ok(Incoming::Unreadable { .. })));
+    }
+
+    #[test]
+    fn frames_missing_required_fields_are_incomplete() {
+        let reaction = r#"{"messageType":"reaction","data":"{\"messageId\":\"a\",\"emoji\":\"👍\"}"}"#;
+        assert_eq!(decode_frame(reaction).err(), Some(FrameError::Incomplete("reaction without a sender")));
+        let ack = r#"{"messageType":"ack"}"#;
+        assert_eq!(decode_frame(ack).err(), Some(FrameError::Incomplete("ack without an id")));
+        let pong = r#"{"messageType":"pong","data":"soon"}"#;
+        assert_eq!(decode_frame(pong).err(), Some(FrameError::Incomplete("pong without a timestamp")));
+    }
+
+    #[test]
+    fn history_keeps_unreadable_entries_as_errors() {
+        let raw = r#"{"messageType":"history","before":10,"dataArray":["{\"from\":\"ann\",\"message\":\"hi\",\"time\":1}","nope"]}"#;
+        match decode_frame(raw) {
+            Ok(Incoming::History { before, entries, .. }) => {
+                assert_eq!(before, Some(10.0));
+                assert_eq!(entries.len(), 2);
+                assert!(entries[0].is_ok());
+                assert!(entries[1].is_err());
+            }
+            _ => panic!("expected a history page"),
+        }
+    }
+
+    fn profile(name: &str, origin: &str, avatar: &str) -> UserProfile {
+        UserProfile {
+            origin: origin.into(),
+            avatar: avatar.into(),
+            ..UserProfile::new(name, &ChatConfig::default())
+        }
+    }
+
+    #[test]
+    fn messages_before_the_users_list_use_the_default_profile() {
+        let m = message(r#"{"from":"ann","message":"hi","time":1}"#);
+        let mut users = Vec::new();
+        let mut profiles = HashMap::new();
+        assert!(sender_profile(&users, &profiles, &m).is_none());
+
+        // What push_message records for a sender nobody has listed yet.
+        profiles.insert("ann".to_string(), profile("ann", "", "default"));
+        assert_eq!(sender_profile(&users, &profiles, &m).map(|u| u.avatar.as_str()), Some("default"));
+
+        users.push(profile("ann", "ws://a", "real"));
+        assert_eq!(sender_profile(&users, &profiles, &m).map(|u| u.avatar.as_str()), Some("real"));
+    }
+
+    #[test]
+    fn text_renders_rich_unless_plain_text_is_on() {
+        let m = message(r#"{"from":"ann","message":"**hi**","time":1}"#);
+        assert_eq!(message_content(&m, false, false, false), MessageContent::Rich);
+        assert_eq!(message_content(&m, true, false, false), MessageContent::Plain);
+    }
+
+    #[test]
+    fn system_lines_are_never_embedded() {
+        let mut m = message(r#"{"from":"","message":"https://x.test/cat.gif","time":1}"#);
+        m.kind = MessageKind::System;
+        assert_eq!(message_content(&m, false, true, false), MessageContent::System);
+    }
+
+    #[test]
+    fn non_image_attachments_become_file_cards() {
+        let m = message(
+            r#"{"from":"ann","message":"data:application/pdf;base64,AA","time":1,"attachment":{"name":"a.pdf","mimeType":"application/pdf","size":2}}"#,
+        );
+        match message_content(&m, false, false, false) {
+            MessageContent::File(meta) => assert_eq!(meta.name, "a.pdf"),
+            other => panic!("expected a file card, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn data_url_images_go_through_the_renderers() {
+        let m = message(r#"{"from":"ann","message":"data:image/png;base64,AA","time":1}"#);
+        assert_eq!(message_content(&m, false, false, false), MessageContent::Rich);
+    }
+
+    #[test]
+    fn videos_get_a_player() {
+        let m = message(r#"{"from":"ann","message":"https://x.test/clip.mp4?t=3","time":1}"#);
+        assert_eq!(message_content(&m, false, false, false), MessageContent::Video);
+    }
+
+    #[test]
+    fn media_links_hold_back_media_until_loaded() {
+        let m = message(r#"{"from":"ann","message":"data:image/gif;base64,AA","time":1}"#);
+        assert_eq!(message_content(&m, false, true, false), MessageContent::MediaLink(MediaKind::Gif));
+        assert_eq!(message_content(&m, false, true, true), MessageContent::Rich);
+    }
+
+    #[test]
+    fn delivered_count_shows_without_an_ack() {
+        assert_eq!(delivery_note(None, Some(3)).as_deref(), Some("✓ Delivered to 3"));
+        assert_eq!(delivery_note(None, Some(0)), None);
+        assert_eq!(delivery_note(None, None), None);
+    }
+
+    #[test]
+    fn acked_messages_show_the_count_or_sent() {
+        assert_eq!(delivery_note(Some(Delivery::Sent), Some(2)).as_deref(), Some("✓ Delivered to 2"));
+        assert_eq!(delivery_note(Some(Delivery::Sent), Some(0)).as_deref(), Some("✓ Sent"));
+        assert_eq!(delivery_note(Some(Delivery::Sending), Some(2)).as_deref(), Some("Sending…"));
+        assert_eq!(delivery_note(Some(Delivery::Failed), Some(2)).as_deref(), Some("Not delivered"));
+    }
+
+    fn users_frame(raw: &str) -> (Vec<String>, Vec<UserDetails>) {
+        match decode_frame(raw) {
+            Ok(Incoming::Users { names, details }) => (names, details),
+            _ => panic!("expected a user list"),
+        }
+    }
+
+    #[test]
+    fn users_frames_report_joins_and_leaves_after_the_first() {
+        let (names, details) = users_frame(r#"{"messageType":"users","dataArray":["me","ann","bob"]}"#);
+        assert_eq!(roster_changes(&[], "ws://a", &names, "me"), (vec![], vec![]));
+        let users = roster(&[], "ws://a", &names, &details, 1.0, &ChatConfig::default());
+        assert_eq!(users.iter().map(|u| u.name.as_str()).collect::<Vec<_>>(), ["me", "ann", "bob"]);
+
+        let (names, _) = users_frame(r#"{"messageType":"users","dataArray":["me","bob","cy"]}"#);
+        assert_eq!(roster_changes(&users, "ws://a", &names, "me"), (vec!["cy".into()], vec!["ann".into()]));
+        // Another server's list says nothing about this one's users.
+        assert_eq!(roster_changes(&users, "ws://b", &names, "me"), (vec![], vec![]));
+    }
+
+    #[test]
+    fn users_frames_keep_known_profiles_and_stamp_who_went_offline() {
+        let users = vec![profile("ann", "ws://a", "custom")];
+        let (names, details) = users_frame(
+            r#"{"messageType":"users","includesOffline":true,"dataArray":["ann"],"data":"[{\"nick\":\"ann\",\"online\":false}]"}"#,
+        );
+        let updated = roster(&users, "ws://a", &names, &details, 42.0, &ChatConfig::default());
+        assert_eq!(updated[0].avatar, "custom");
+        assert!(!updated[0].online);
+        assert_eq!(updated[0].last_seen, Some(42.0));
+    }
+
+    #[test]
+    fn message_frames_fold_repeats_but_not_thread_replies() {
+        let incoming = |raw: &str| match decode_frame(raw) {
+            Ok(Incoming::Message(m)) => *m,
+            _ => panic!("expected a message"),
+        };
+        let mut messages = vec![];
+        let first = incoming(r#"{"messageType":"message","data":"{\"from\":\"ann\",\"message\":\"hi\",\"time\":1}"}"#);
+        let first = fold_repeat(&mut messages, first, true);
+        messages.extend(first);
+        let again = incoming(r#"{"messageType":"message","data":"{\"from\":\"ann\",\"message\":\"HI \",\"time\":2}"}"#);
+        assert!(fold_repeat(&mut messages, again, true).is_none());
+        assert_eq!(messages[0].repeats, 1);
+
+        let reply = incoming(
+            r#"{"messageType":"message","data":"{\"from\":\"ann\",\"message\":\"hi\",\"time\":3,\"threadId\":\"ann-1\"}"}"#,
+        );
+        assert!(fold_repeat(&mut messages, reply, true).is_some());
+        let off = incoming(r#"{"messageType":"message","data":"{\"from\":\"ann\",\"message\":\"hi\",\"time\":4}"}"#);
+        assert!(fold_repeat(&mut messages, off, false).is_some());
+    }
+
+    #[test]
+    fn reaction_frames_add_and_take_back_reactions() {
+        let mut messages = vec![message(r#"{"id":"a","from":"me","message":"hi","time":1}"#)];
+        let reaction = |raw: &str| match decode_frame(raw) {
+            Ok(Incoming::Reaction { message_id, emoji, from, remove }) => (message_id, emoji, from, remove),
+            _ => panic!("expected a reaction"),
+        };
+        let (id, emoji, from, remove) =
+            reaction(r#"{"messageType":"reaction","data":"{\"messageId\":\"a\",\"emoji\":\"👍\",\"from\":\"bob\"}"}"#);
+        assert_eq!(apply_reaction(&mut messages, &id, &emoji, &from, remove, "me"), (true, true));
+        // The same reaction twice is a no-op.
+        assert_eq!(apply_reaction(&mut messages, &id, &emoji, &from, remove, "me"), (false, false));
+        assert_eq!(messages[0].reactions["👍"], ["bob"]);
+
+        let (id, emoji, from, remove) = reaction(
+            r#"{"messageType":"reaction","data":"{\"messageId\":\"a\",\"emoji\":\"👍\",\"from\":\"bob\",\"remove\":true}"}"#,
+        );
+        assert_eq!(apply_reaction(&mut messages, &id, &emoji, &from, remove, "me"), (true, false));
+        assert!(messages[0].reactions.is_empty());
+        assert_eq!(apply_reaction(&mut messages, "gone", &emoji, &from, false, "me"), (false, false));
+    }
+
+    #[test]
+    fn edit_frames_replace_the_text_and_the_search_words() {
+        let mut messages = vec![message(r#"{"id":"a","from":"ann","message":"old words","time":1}"#)];
+        let mut index = SearchIndex::default();
+        index.index_message(0, "old words");
+        let (id, text, from) = match decode_frame(
+            r#"{"messageType":"edit","data":"{\"messageId\":\"a\",\"message\":\"new text\",\"from\":\"ann\"}"}"#,
+        ) {
+            Ok(Incoming::Edit { message_id, message, from }) => (message_id, message, from),
+            _ => panic!("expected an edit"),
+        };
+        assert!(!apply_edit(&mut messages, &mut index, &id, text.clone(), "bob"));
+        assert!(apply_edit(&mut messages, &mut index, &id, text, &from));
+        assert_eq!(messages[0].message, "new text");
+        assert!(messages[0].edited);
+        assert!(index.query("old").unwrap().is_empty());
+        assert!(index.query("new").unwrap().contains(&0));
+    }
+
+    #[test]
+    fn only_malformed_frames_are_reported() {
+        let origin = || "ws://a".to_string();
+        let malformed = decode_frame("{not json").err().unwrap();
+        assert!(matches!(
+            rejected_frame(origin(), malformed),
+            Some(ChatEvent::FrameRejected { server, .. }) if server == "ws://a"
+        ));
+        let incomplete = decode_frame(r#"{"messageType":"pong"}"#).err().unwrap();
+        assert_eq!(rejected_frame(origin(), incomplete), None);
+    }
+
+    #[test]
+    fn uri_encoding_matches_javascript() {
+        assert_eq!(encode_uri_component("a b<'>"), "a%20b%3C'%3E");
+        assert_eq!(encode_uri_component("#x='é'"), "%23x%3D'%C3%A9'");
+        assert_eq!(encode_uri_component("A-z_0.!~*()"), "A-z_0.!~*()");
+    }
+}
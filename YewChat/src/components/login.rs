@@ -8,7 +8,7 @@ use crate::User;
 
 #[function_component(Login)]
 pub fn login() -> Html {
-    let username = use_state(|| String::new());
+    let username = use_state(String::new);
     let user = use_context::<User>().expect("No context found.");
 
     let oninput = {
@@ -31,7 +31,7 @@ pub fn login() -> Html {
             <div class="container mx-auto flex flex-col justify-center items-center">
                 <form class="m-4 flex">
                     <input {oninput} class="rounded-l-lg p-4 border-t mr-0 border-b border-l text-gray-800 border-gray-200 bg-white" placeholder="Username" />
-                    <Link<Route> to={Route::Chat}> <button {onclick} disabled={username.len()<1} class="px-8 rounded-r-lg bg-red-800	  text-white font-bold p-4 uppercase border-violet-600 border-t border-b border-r" >{"Enter the abyss!"}</button></Link<Route>>
+                    <Link<Route> to={Route::Chat}> <button {onclick} disabled={username.is_empty()} class="px-8 rounded-r-lg bg-red-800	  text-white font-bold p-4 uppercase border-violet-600 border-t border-b border-r" >{"Enter the abyss!"}</button></Link<Route>>
                 </form>
             </div>
         </div>
@@ -1,2 +1,4 @@
 pub mod chat;
-pub mod login;
\ No newline at end of file
+pub mod login;
+pub mod renderers;
+pub mod settings;
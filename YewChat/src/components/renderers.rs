@@ -0,0 +1,432 @@
+use yew::prelude::*;
+
+// Keeps the sender's line breaks and wraps long words instead of overflowing.
+const TEXT_CLASS: &str = "text-sm text-[var(--chat-text)] whitespace-pre-wrap break-words";
+
+// Turns a message body into rich content. Renderers run in order and the
+// first one that returns `Some` wins; plain text is the fallback. The built-in
+// ones share `render_inline`, so inline markup combines with whichever wins.
+pub trait MessageRenderer {
+    fn render(&self, msg: &str) -> Option<Html>;
+}
+
+pub struct GifRenderer;
+
+impl MessageRenderer for GifRenderer {
+    fn render(&self, msg: &str) -> Option<Html> {
+        (msg.ends_with(".gif") || msg.starts_with("data:image/"))
+            .then(|| html! { <img class="mt-2 rounded" src={msg.to_string()} /> })
+    }
+}
+
+pub struct AttachmentRenderer;
+
+impl MessageRenderer for AttachmentRenderer {
+    fn render(&self, msg: &str) -> Option<Html> {
+        msg.starts_with("data:").then(|| html! {
+            <a class="text-sm text-[var(--chat-highlight)] underline" href={msg.to_string()} download="attachment">{"📎 Attachment"}</a>
+        })
+    }
+}
+
+// Byte ranges of `@name` tokens that start a word.
+fn mention_ranges(text: &str) -> Vec<(usize, usize)> {
+    let mut ranges = vec![];
+    for (i, c) in text.char_indices() {
+        let word_start = text[..i].chars().next_back().is_none_or(char::is_whitespace);
+        if c != '@' || !word_start {
+            continue;
+        }
+        let rest = &text[i + 1..];
+        let len = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(rest.len());
+        if len > 0 {
+            ranges.push((i, i + 1 + len));
+        }
+    }
+    ranges
+}
+
+fn is_broadcast_mention(token: &str) -> bool {
+    matches!(token, "@everyone" | "@here")
+}
+
+const TWEMOJI_BASE: &str = "https://cdn.jsdelivr.net/gh/twitter/twemoji@14.0.2/assets/svg/";
+
+fn is_emoji(c: char) -> bool {
+    matches!(c as u32, 0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2B00..=0x2BFF)
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    matches!(c as u32, 0x1F1E6..=0x1F1FF)
+}
+
+// Variation selectors, keycaps and skin tones belong to the emoji before them.
+fn is_emoji_modifier(c: char) -> bool {
+    matches!(c as u32, 0xFE0F | 0x20E3 | 0x1F3FB..=0x1F3FF)
+}
+
+// Splits text into (is_emoji, text) runs, one run per emoji including its ZWJ
+// sequence, modifiers and flag pairs. Emoji inside backticks are left alone.
+fn emoji_runs(text: &str) -> Vec<(bool, &str)> {
+    let mut runs = vec![];
+    let mut chars = text.char_indices().peekable();
+    let mut start = 0;
+    let mut in_code = false;
+    while let Some((i, c)) = chars.next() {
+        if c == '`' {
+            in_code = !in_code;
+        }
+        if in_code || !is_emoji(c) {
+            continue;
+        }
+        let mut end = i + c.len_utf8();
+        if is_regional_indicator(c) {
+            if let Some(&(j, next)) = chars.peek().filter(|(_, n)| is_regional_indicator(*n)) {
+                chars.next();
+                end = j + next.len_utf8();
+            }
+        }
+        loop {
+            match chars.peek().copied() {
+                Some((j, next)) if is_emoji_modifier(next) => {
+                    chars.next();
+                    end = j + next.len_utf8();
+                }
+                Some((j, '\u{200D}')) => {
+                    let after = j + '\u{200D}'.len_utf8();
+                    match text[after..].chars().next() {
+                        Some(joined) if is_emoji(joined) => {
+                            chars.next();
+                            chars.next();
+                            end = after + joined.len_utf8();
+                        }
+                        _ => break,
+                    }
+                }
+                _ => break,
+            }
+        }
+        runs.push((false, &text[start..i]));
+        runs.push((true, &text[i..end]));
+        start = end;
+    }
+    runs.push((false, &text[start..]));
+    runs.retain(|(_, text)| !text.is_empty());
+    runs
+}
+
+// Twemoji names files by code point, dropping the variation selector unless
+// the emoji is a ZWJ sequence.
+fn twemoji_url(emoji: &str) -> String {
+    let keep_selector = emoji.contains('\u{200D}');
+    let name: Vec<String> = emoji
+        .chars()
+        .filter(|c| keep_selector || *c != '\u{FE0F}')
+        .map(|c| format!("{:x}", c as u32))
+        .collect();
+    format!("{}{}.svg", TWEMOJI_BASE, name.join("-"))
+}
+
+fn render_emoji(text: &str, twemoji: bool) -> Html {
+    if !twemoji {
+        return html! { {text} };
+    }
+    emoji_runs(text)
+        .into_iter()
+        .map(|(emoji, run)| {
+            if emoji {
+                html! { <img class="inline-block w-[1.2em] h-[1.2em] align-[-0.2em]" src={twemoji_url(run)} alt={run.to_string()} draggable="false" /> }
+            } else {
+                html! { {run} }
+            }
+        })
+        .collect()
+}
+
+// Plain text with mentions picked out; `@everyone` and `@here` stand out more.
+fn render_text(text: &str, twemoji: bool) -> Html {
+    let mut parts = vec![];
+    let mut last = 0;
+    for (start, end) in mention_ranges(text) {
+        parts.push(render_emoji(&text[last..start], twemoji));
+        let token = &text[start..end];
+        let class = if is_broadcast_mention(token) {
+            "px-1 rounded font-bold text-white bg-[var(--chat-accent)]"
+        } else {
+            "font-semibold text-[var(--chat-highlight)]"
+        };
+        parts.push(html! { <span {class}>{token}</span> });
+        last = end;
+    }
+    parts.push(render_emoji(&text[last..], twemoji));
+    parts.into_iter().collect()
+}
+
+#[derive(Default)]
+pub struct MentionRenderer {
+    pub twemoji: bool,
+}
+
+impl MessageRenderer for MentionRenderer {
+    fn render(&self, msg: &str) -> Option<Html> {
+        if mention_ranges(msg).is_empty() {
+            return None;
+        }
+        Some(html! { <div class={TEXT_CLASS}>{render_text(msg, self.twemoji)}</div> })
+    }
+}
+
+// Catches messages with emoji that no other renderer took, so they still get
+// Twemoji images. Only installed when that setting is on.
+pub struct EmojiRenderer;
+
+impl MessageRenderer for EmojiRenderer {
+    fn render(&self, msg: &str) -> Option<Html> {
+        if !emoji_runs(msg).iter().any(|(emoji, _)| *emoji) {
+            return None;
+        }
+        Some(html! { <div class={TEXT_CLASS}>{render_emoji(msg, true)}</div> })
+    }
+}
+
+pub fn mentions_everyone(msg: &str) -> bool {
+    mention_ranges(msg)
+        .into_iter()
+        .any(|(start, end)| is_broadcast_mention(&msg[start..end]))
+}
+
+// Splits a message into (is_spoiler, text) runs. `||` inside backtick code
+// and unmatched `||` stay as they are.
+fn spoiler_runs(msg: &str) -> Vec<(bool, &str)> {
+    let bytes = msg.as_bytes();
+    let mut runs = vec![];
+    let mut start = 0;
+    let mut i = 0;
+    let mut in_code = false;
+    while i < bytes.len() {
+        if bytes[i] == b'`' {
+            in_code = !in_code;
+        } else if !in_code && bytes[i..].starts_with(b"||") {
+            match msg[i + 2..].find("||") {
+                Some(len) if len > 0 => {
+                    runs.push((false, &msg[start..i]));
+                    runs.push((true, &msg[i + 2..i + 2 + len]));
+                    i += len + 4;
+                    start = i;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        i += 1;
+    }
+    runs.push((false, &msg[start..]));
+    runs.retain(|(_, text)| !text.is_empty());
+    runs
+}
+
+#[derive(Default)]
+pub struct SpoilerRenderer {
+    pub twemoji: bool,
+}
+
+impl MessageRenderer for SpoilerRenderer {
+    fn render(&self, msg: &str) -> Option<Html> {
+        if !spoiler_runs(msg).iter().any(|(spoiler, _)| *spoiler) {
+            return None;
+        }
+        Some(html! { <div class={TEXT_CLASS}>{render_inline(msg, self.twemoji)}</div> })
+    }
+}
+
+// Everything that can sit inside a line: spoilers, mentions and emoji. Block
+// content goes through this too, so they also work in lists and tables.
+fn render_inline(text: &str, twemoji: bool) -> Html {
+    // A hidden checkbox per spoiler keeps the reveal state in the DOM.
+    spoiler_runs(text)
+        .into_iter()
+        .map(|(spoiler, text)| {
+            if spoiler {
+                html! {
+                    <label title="Spoiler" class="cursor-pointer">
+                        <input type="checkbox" class="peer hidden" />
+                        <span class="px-0.5 rounded bg-[var(--chat-text)] text-transparent select-none peer-checked:bg-[var(--chat-input)] peer-checked:text-[var(--chat-text)] peer-checked:select-auto">{text}</span>
+                    </label>
+                }
+            } else {
+                render_text(text, twemoji)
+            }
+        })
+        .collect()
+}
+
+enum Block<'a> {
+    Text(Vec<&'a str>),
+    Bullets(Vec<&'a str>),
+    Numbered(Vec<&'a str>),
+    Table(Vec<&'a str>, Vec<Vec<&'a str>>),
+}
+
+fn bullet_item(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    line.strip_prefix("- ").or_else(|| line.strip_prefix("* "))
+}
+
+fn numbered_item(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    let digits = line.find(|c: char| !c.is_ascii_digit())?;
+    (digits > 0).then(|| line[digits..].strip_prefix(". "))?
+}
+
+fn table_row(line: &str) -> Option<Vec<&str>> {
+    let line = line.trim();
+    if line.len() < 2 || !line.starts_with('|') || !line.ends_with('|') {
+        return None;
+    }
+    Some(line[1..line.len() - 1].split('|').map(str::trim).collect())
+}
+
+fn is_table_separator(line: &str) -> bool {
+    table_row(line).is_some_and(|cells| {
+        cells.iter().all(|cell| {
+            cell.contains('-') && cell.chars().all(|c| c == '-' || c == ':')
+        })
+    })
+}
+
+// Groups lines into lists, pipe tables (a header row followed by a `---`
+// row) and the plain text between them.
+fn blocks(msg: &str) -> Vec<Block<'_>> {
+    let lines: Vec<&str> = msg.lines().collect();
+    let mut blocks = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if let (Some(header), true) = (table_row(line), lines.get(i + 1).is_some_and(|l| is_table_separator(l))) {
+            i += 2;
+            let mut rows = vec![];
+            while let Some(row) = lines.get(i).and_then(|l| table_row(l)) {
+                rows.push(row);
+                i += 1;
+            }
+            blocks.push(Block::Table(header, rows));
+            continue;
+        }
+        match (bullet_item(line), numbered_item(line), blocks.last_mut()) {
+            (Some(item), _, Some(Block::Bullets(items))) => items.push(item),
+            (Some(item), _, _) => blocks.push(Block::Bullets(vec![item])),
+            (_, Some(item), Some(Block::Numbered(items))) => items.push(item),
+            (_, Some(item), _) => blocks.push(Block::Numbered(vec![item])),
+            (_, _, Some(Block::Text(text))) => text.push(line),
+            _ => blocks.push(Block::Text(vec![line])),
+        }
+        i += 1;
+    }
+    blocks
+}
+
+const CELL_CLASS: &str = "px-2 py-1 border border-[var(--chat-border)] text-left align-top";
+
+// Markdown-style lists and tables. Everything goes through `html!`, which
+// escapes text, so nothing the sender writes is interpreted as markup.
+#[derive(Default)]
+pub struct BlockRenderer {
+    pub twemoji: bool,
+}
+
+impl MessageRenderer for BlockRenderer {
+    fn render(&self, msg: &str) -> Option<Html> {
+        let twemoji = self.twemoji;
+        let blocks = blocks(msg);
+        if blocks.iter().all(|b| matches!(b, Block::Text(_))) {
+            return None;
+        }
+        Some(html! {
+            <div class="space-y-1">
+                {
+                    blocks.into_iter().map(|block| match block {
+                        Block::Text(lines) => html! { <div class={TEXT_CLASS}>{render_inline(&lines.join("\n"), twemoji)}</div> },
+                        Block::Bullets(items) => html! {
+                            <ul class={classes!(TEXT_CLASS, "list-disc", "pl-5")}>
+                                { items.into_iter().map(|item| html! { <li>{render_inline(item, twemoji)}</li> }).collect::<Html>() }
+                            </ul>
+                        },
+                        Block::Numbered(items) => html! {
+                            <ol class={classes!(TEXT_CLASS, "list-decimal", "pl-5")}>
+                                { items.into_iter().map(|item| html! { <li>{render_inline(item, twemoji)}</li> }).collect::<Html>() }
+                            </ol>
+                        },
+                        Block::Table(header, rows) => html! {
+                            <div class="overflow-x-auto">
+                                <table class="text-sm text-[var(--chat-text)] border-collapse">
+                                    <thead class="bg-[var(--chat-panel)]">
+                                        <tr>
+                                            { header.into_iter().map(|cell| html! { <th class={classes!(CELL_CLASS, "font-semibold")}>{render_inline(cell, twemoji)}</th> }).collect::<Html>() }
+                                        </tr>
+                                    </thead>
+                                    <tbody>
+                                        {
+                                            rows.into_iter().map(|row| html! {
+                                                <tr>
+                                                    { row.into_iter().map(|cell| html! { <td class={CELL_CLASS}>{render_inline(cell, twemoji)}</td> }).collect::<Html>() }
+                                                </tr>
+                                            }).collect::<Html>()
+                                        }
+                                    </tbody>
+                                </table>
+                            </div>
+                        },
+                    }).collect::<Html>()
+                }
+            </div>
+        })
+    }
+}
+
+pub fn default_renderers(twemoji: bool) -> Vec<Box<dyn MessageRenderer>> {
+    let mut renderers: Vec<Box<dyn MessageRenderer>> = vec![
+        Box::new(GifRenderer),
+        Box::new(AttachmentRenderer),
+        // Blocks first: they render spoilers inside items and cells too.
+        Box::new(BlockRenderer { twemoji }),
+        Box::new(SpoilerRenderer { twemoji }),
+        Box::new(MentionRenderer { twemoji }),
+    ];
+    if twemoji {
+        renderers.push(Box::new(EmojiRenderer));
+    }
+    renderers
+}
+
+// Plain mode: no images or styling, only text and ordinary links so
+// everything stays readable by screen readers and reachable by keyboard.
+pub fn render_plain(msg: &str) -> Html {
+    if msg.starts_with("data:") {
+        return html! {
+            <a class="text-sm underline" href={msg.to_string()} download="attachment">{"Attachment (download)"}</a>
+        };
+    }
+    let mut parts = vec![];
+    let mut last = 0;
+    let mut cursor = 0;
+    for word in msg.split_whitespace() {
+        let start = cursor + msg[cursor..].find(word).unwrap_or_default();
+        cursor = start + word.len();
+        if word.starts_with("http://") || word.starts_with("https://") {
+            parts.push(html! { {&msg[last..start]} });
+            parts.push(html! { <a class="underline" href={word.to_string()} target="_blank" rel="noopener noreferrer">{word}</a> });
+            last = cursor;
+        }
+    }
+    parts.push(html! { {&msg[last..]} });
+    html! { <div class={TEXT_CLASS}>{parts.into_iter().collect::<Html>()}</div> }
+}
+
+pub fn render_message(renderers: &[Box<dyn MessageRenderer>], msg: &str) -> Html {
+    renderers
+        .iter()
+        .find_map(|r| r.render(msg))
+        .unwrap_or_else(|| html! { <div class={TEXT_CLASS}>{msg.to_string()}</div> })
+}
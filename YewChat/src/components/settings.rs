@@ -0,0 +1,255 @@
+use web_sys::{HtmlInputElement, HtmlSelectElement};
+use yew::prelude::*;
+
+use crate::config::ChatConfig;
+use crate::services::platform;
+use crate::services::settings::{AvatarShape, ClockFormat, SendKey, Settings as Preferences, SoundChoice, ThemeMode};
+use crate::SettingsContext;
+
+#[derive(Properties, PartialEq)]
+pub struct SettingsProps {
+    pub on_close: Callback<()>,
+}
+
+#[function_component(Settings)]
+pub fn settings(props: &SettingsProps) -> Html {
+    let handle = use_context::<SettingsContext>().expect("No settings context found.");
+    let config = use_context::<ChatConfig>().unwrap_or_default();
+
+    let toggle = |apply: fn(&mut Preferences)| {
+        let handle = handle.clone();
+        Callback::from(move |_: Event| {
+            let mut next = (*handle).clone();
+            apply(&mut next);
+            next.save();
+            handle.set(next);
+        })
+    };
+
+    let mut rows: Vec<(&str, bool, Callback<Event>)> = vec![
+        ("Play a sound on new messages", handle.sound, toggle(|s| s.sound = !s.sound)),
+        (
+            "Light theme",
+            handle.theme == ThemeMode::Light,
+            toggle(|s| {
+                s.theme = match s.theme {
+                    ThemeMode::Dark => ThemeMode::Light,
+                    ThemeMode::Light => ThemeMode::Dark,
+                }
+            }),
+        ),
+        ("Compact messages", handle.compact, toggle(|s| s.compact = !s.compact)),
+        (
+            "Plain text mode (no images or animations)",
+            handle.plain_text,
+            toggle(|s| s.plain_text = !s.plain_text),
+        ),
+        (
+            "Show images and videos as links (click to load)",
+            handle.media_links,
+            toggle(|s| s.media_links = !s.media_links),
+        ),
+        ("Consistent emoji (Twemoji)", handle.twemoji, toggle(|s| s.twemoji = !s.twemoji)),
+        (
+            "Reduce motion and effects",
+            handle.reduce_motion,
+            toggle(|s| s.reduce_motion = !s.reduce_motion),
+        ),
+        ("Show avatars", handle.show_avatars, toggle(|s| s.show_avatars = !s.show_avatars)),
+        (
+            "Collapse repeated messages",
+            handle.collapse_repeats,
+            toggle(|s| s.collapse_repeats = !s.collapse_repeats),
+        ),
+        (
+            "Show timestamps only on hover",
+            handle.timestamps_on_hover,
+            toggle(|s| s.timestamps_on_hover = !s.timestamps_on_hover),
+        ),
+        (
+            "Notify on @everyone and @here",
+            handle.notify_everyone,
+            toggle(|s| s.notify_everyone = !s.notify_everyone),
+        ),
+        (
+            "Announce people joining",
+            handle.join_toasts,
+            toggle(|s| s.join_toasts = !s.join_toasts),
+        ),
+        (
+            "Show joins and leaves in the chat",
+            handle.join_lines,
+            toggle(|s| s.join_lines = !s.join_lines),
+        ),
+        (
+            "Show unreadable messages (debug)",
+            handle.show_unreadable,
+            toggle(|s| s.show_unreadable = !s.show_unreadable),
+        ),
+    ];
+    // Browsers without the Notification API don't get a toggle that can't work.
+    if platform::notifications_available() {
+        rows.insert(
+            1,
+            (
+                "Desktop notifications",
+                handle.notifications,
+                toggle(|s| {
+                    s.notifications = !s.notifications;
+                    if s.notifications {
+                        platform::request_notifications();
+                    }
+                }),
+            ),
+        );
+    }
+
+    let set_gif_api_key = {
+        let handle = handle.clone();
+        Callback::from(move |e: Event| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            let mut next = (*handle).clone();
+            next.gif_api_key = input.value().trim().to_string();
+            next.save();
+            handle.set(next);
+        })
+    };
+
+    let set_avatar_shape = {
+        let handle = handle.clone();
+        Callback::from(move |e: Event| {
+            let select: HtmlSelectElement = e.target_unchecked_into();
+            let index = select.selected_index().max(0) as usize;
+            let mut next = (*handle).clone();
+            next.avatar_shape = AvatarShape::ALL.get(index).copied().unwrap_or_default();
+            next.save();
+            handle.set(next);
+        })
+    };
+
+    let set_clock = {
+        let handle = handle.clone();
+        Callback::from(move |e: Event| {
+            let select: HtmlSelectElement = e.target_unchecked_into();
+            let index = select.selected_index().max(0) as usize;
+            let mut next = (*handle).clone();
+            next.clock = ClockFormat::ALL.get(index).copied().unwrap_or_default();
+            next.save();
+            handle.set(next);
+        })
+    };
+
+    let set_send_key = {
+        let handle = handle.clone();
+        Callback::from(move |e: Event| {
+            let select: HtmlSelectElement = e.target_unchecked_into();
+            let index = select.selected_index().max(0) as usize;
+            let mut next = (*handle).clone();
+            next.send_key = SendKey::ALL.get(index).copied().unwrap_or_default();
+            next.save();
+            handle.set(next);
+        })
+    };
+
+    // A sound picker and volume slider for one kind of message.
+    let sound_row = |label: &str, choice: &SoundChoice, field: fn(&mut Preferences) -> &mut SoundChoice| {
+        let set_sound = {
+            let handle = handle.clone();
+            let sounds = config.sounds.clone();
+            Callback::from(move |e: Event| {
+                let select: HtmlSelectElement = e.target_unchecked_into();
+                let index = select.selected_index().max(0) as usize;
+                let mut next = (*handle).clone();
+                field(&mut next).sound = sounds.get(index).map(|(l, _)| l.clone()).unwrap_or_default();
+                next.save();
+                handle.set(next);
+            })
+        };
+        let set_volume = {
+            let handle = handle.clone();
+            Callback::from(move |e: Event| {
+                let input: HtmlInputElement = e.target_unchecked_into();
+                let mut next = (*handle).clone();
+                field(&mut next).volume = input.value().parse().unwrap_or(100);
+                next.save();
+                handle.set(next);
+            })
+        };
+        let current = config.sounds.iter().position(|(name, _)| *name == choice.sound).unwrap_or(0);
+        html! {
+            <label class="flex items-center gap-2 pt-2 text-sm text-[var(--chat-text)]">
+                {label}
+                <select onchange={set_sound} class="ml-auto py-1 px-2 text-xs rounded bg-[var(--chat-input)] outline-none">
+                    {
+                        config.sounds.iter().enumerate().map(|(i, (name, _))| html! {
+                            <option selected={i == current}>{name}</option>
+                        }).collect::<Html>()
+                    }
+                </select>
+                <input type="range" min="0" max="100" value={choice.volume.to_string()} onchange={set_volume} aria-label={format!("{} volume", label)} class="w-20" />
+            </label>
+        }
+    };
+    let message_sound = sound_row("Message sound", &handle.message_sound, |s| &mut s.message_sound);
+    let mention_sound = sound_row("Mention sound", &handle.mention_sound, |s| &mut s.mention_sound);
+
+    let close = {
+        let on_close = props.on_close.clone();
+        Callback::from(move |_| on_close.emit(()))
+    };
+
+    html! {
+        <div class="fixed inset-0 z-20 flex items-center justify-center bg-black/60">
+            <div role="dialog" aria-label="Settings" class="w-80 p-4 rounded-lg border border-[var(--chat-border)] bg-[var(--chat-surface)]">
+                <div class="flex items-center mb-3">
+                    <div class="text-xl">{"⚙ Settings"}</div>
+                    <button onclick={close} aria-label="Close settings" class="ml-auto px-2 rounded bg-transparent border-none">{"×"}</button>
+                </div>
+                {
+                    rows.into_iter().map(|(label, checked, onchange)| html! {
+                        <label class="flex items-center gap-2 py-1 text-sm text-[var(--chat-text)] cursor-pointer">
+                            <input type="checkbox" {checked} {onchange} />
+                            {label}
+                        </label>
+                    }).collect::<Html>()
+                }
+                {message_sound}
+                {mention_sound}
+                <label class="flex items-center gap-2 pt-2 text-sm text-[var(--chat-text)]">
+                    {"Avatar shape"}
+                    <select onchange={set_avatar_shape} class="ml-auto py-1 px-2 text-xs rounded bg-[var(--chat-input)] outline-none">
+                        {
+                            AvatarShape::ALL.iter().map(|shape| html! {
+                                <option selected={*shape == handle.avatar_shape}>{shape.label()}</option>
+                            }).collect::<Html>()
+                        }
+                    </select>
+                </label>
+                <label class="flex items-center gap-2 pt-2 text-sm text-[var(--chat-text)]">
+                    {"Clock"}
+                    <select onchange={set_clock} class="ml-auto py-1 px-2 text-xs rounded bg-[var(--chat-input)] outline-none">
+                        {
+                            ClockFormat::ALL.iter().map(|clock| html! {
+                                <option selected={*clock == handle.clock}>{clock.label()}</option>
+                            }).collect::<Html>()
+                        }
+                    </select>
+                </label>
+                <label class="flex items-center gap-2 pt-2 text-sm text-[var(--chat-text)]">
+                    {"Send with"}
+                    <select onchange={set_send_key} class="ml-auto py-1 px-2 text-xs rounded bg-[var(--chat-input)] outline-none">
+                        {
+                            SendKey::ALL.iter().map(|key| html! {
+                                <option selected={*key == handle.send_key}>{key.label()}</option>
+                            }).collect::<Html>()
+                        }
+                    </select>
+                </label>
+                <label class="block pt-2 text-sm text-[var(--chat-text)]">
+                    {"Tenor API key (for GIF search)"}
+                    <input type="password" value={handle.gif_api_key.clone()} onchange={set_gif_api_key} class="w-full mt-1 py-1 px-2 text-xs rounded bg-[var(--chat-input)] outline-none" />
+                </label>
+            </div>
+        </div>
+    }
+}
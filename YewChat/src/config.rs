@@ -0,0 +1,86 @@
+// Tunables for the chat component. Provide one through a
+// `ContextProvider<ChatConfig>` to override them; without a provider the
+// defaults below are used.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChatConfig {
+    // `{name}` is replaced with the username.
+    pub avatar_url: String,
+    // (label, url) pairs to pick notification sounds from; the first is the default.
+    pub sounds: Vec<(String, String)>,
+    pub heartbeat_interval_ms: u32,
+    // Messages kept in local storage.
+    pub history_limit: usize,
+    // Older messages asked for at a time when scrolling up.
+    pub history_page_size: usize,
+    pub toast_duration_ms: u32,
+    pub highlight_duration_ms: u32,
+    pub ack_timeout_ms: u32,
+    // Activity is re-announced at most this often and forgotten after the TTL.
+    // Keep the throttle below `typing_ttl_ms` or the indicator flickers.
+    pub activity_throttle_ms: u32,
+    pub activity_ttl_ms: u32,
+    // Someone who stops sending typing frames has stopped typing after this.
+    pub typing_ttl_ms: u32,
+    // How long an "editing…" marker survives without a stop frame.
+    pub editing_ttl_ms: u32,
+    // Reactions to our messages are collected for this long and shown as one toast.
+    pub reaction_digest_ms: u32,
+    // User lists arriving within this window are coalesced into one update.
+    pub users_debounce_ms: u32,
+    // Keeps raw frames for the traffic panel; on by default in debug builds.
+    pub debug_traffic: bool,
+    pub traffic_log_limit: usize,
+    // Shown in a room nobody has written in yet.
+    pub empty_room_text: String,
+    // Classes for joins, leaves and other lines that aren't someone talking.
+    pub system_message_class: String,
+    // The composer grows with its content up to this many lines, then scrolls.
+    pub composer_max_lines: u32,
+    pub max_attachment_bytes: u64,
+    // MIME types accepted as attachments; `type/*` matches a whole family.
+    pub attachment_types: Vec<String>,
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            avatar_url: "https://avatars.dicebear.com/api/adventurer-neutral/{name}.svg".into(),
+            sounds: vec![("Chime".into(), "/static/sounds/notify.wav".into())],
+            heartbeat_interval_ms: 10_000,
+            history_limit: 200,
+            history_page_size: 50,
+            toast_duration_ms: 4_000,
+            highlight_duration_ms: 2_000,
+            ack_timeout_ms: 10_000,
+            activity_throttle_ms: 2_000,
+            activity_ttl_ms: 5_000,
+            typing_ttl_ms: 3_000,
+            editing_ttl_ms: 15_000,
+            reaction_digest_ms: 3_000,
+            users_debounce_ms: 250,
+            debug_traffic: cfg!(debug_assertions),
+            traffic_log_limit: 200,
+            empty_room_text: "No messages yet — say hello!".into(),
+            system_message_class: "px-3 text-center text-xs italic text-[var(--chat-muted)]".into(),
+            composer_max_lines: 6,
+            max_attachment_bytes: 5 * 1024 * 1024,
+            attachment_types: ["image/*", "audio/*", "video/*", "application/pdf", "text/plain"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+impl ChatConfig {
+    pub fn avatar_url(&self, name: &str) -> String {
+        self.avatar_url.replace("{name}", name)
+    }
+
+    pub fn sound_url(&self, label: &str) -> Option<&str> {
+        self.sounds
+            .iter()
+            .find(|(l, _)| l == label)
+            .or_else(|| self.sounds.first())
+            .map(|(_, url)| url.as_str())
+    }
+}
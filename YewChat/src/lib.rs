@@ -1,7 +1,12 @@
 #![recursion_limit = "512"]
+// Only for the `html!` macro: yew 0.19 expands it into code that current
+// clippy flags as `unnecessary_operation` and `let_unit_value`.
+#![allow(clippy::unnecessary_operation, clippy::let_unit_value)]
 
 mod components;
+mod config;
 mod services;
+mod theme;
 
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -13,6 +18,8 @@ use yew_router::prelude::*;
 
 use components::chat::Chat;
 use components::login::Login;
+use config::ChatConfig;
+use services::settings::Settings;
 
 // When the `wee_alloc` feature is enabled, this uses `wee_alloc` as the global
 // allocator.
@@ -22,18 +29,21 @@ use components::login::Login;
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
-#[derive(Debug, Clone, Copy, PartialEq, Routable)]
+#[derive(Debug, Clone, PartialEq, Routable)]
 pub enum Route {
     #[at("/")]
     Login,
     #[at("/chat")]
     Chat,
+    #[at("/chat/:room")]
+    Room { room: String },
     #[not_found]
     #[at("/404")]
     NotFound,
 }
 
 pub type User = Rc<UserInner>;
+pub type SettingsContext = UseStateHandle<Settings>;
 
 #[derive(Debug, PartialEq)]
 pub struct UserInner {
@@ -47,14 +57,19 @@ fn main() -> Html {
             username: RefCell::new("initial".into()),
         })
     });
+    let settings = use_state(Settings::load);
 
     html! {
         <ContextProvider<User> context={(*ctx).clone()}>
-            <BrowserRouter>
-                <div class="flex w-screen h-screen">
-                    <Switch<Route> render={Switch::render(switch)}/>
-                </div>
-            </BrowserRouter>
+            <ContextProvider<SettingsContext> context={settings}>
+                <ContextProvider<ChatConfig> context={ChatConfig::default()}>
+                    <BrowserRouter>
+                        <div class="flex w-screen h-screen">
+                            <Switch<Route> render={Switch::render(switch)}/>
+                        </div>
+                    </BrowserRouter>
+                </ContextProvider<ChatConfig>>
+            </ContextProvider<SettingsContext>>
         </ContextProvider<User>>
     }
 }
@@ -63,6 +78,7 @@ fn switch(selected_route: &Route) -> Html {
     match selected_route {
         Route::Login => html! {<Login />},
         Route::Chat => html! {<Chat/>},
+        Route::Room { room } => html! {<Chat room={room.clone()}/>},
         Route::NotFound => html! {<h1>{"404 baby"}</h1>},
     }
 }
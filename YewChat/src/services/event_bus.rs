@@ -2,9 +2,16 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use yew_agent::{Agent, AgentLink, Context, HandlerId};
 
+// A raw frame, tagged with the server it came from.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Frame {
+    pub origin: String,
+    pub data: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Request {
-    EventBusMsg(String),
+    EventBusMsg(Frame),
 }
 
 pub struct EventBus {
@@ -16,7 +23,7 @@ impl Agent for EventBus {
     type Reach = Context<Self>;
     type Message = ();
     type Input = Request;
-    type Output = String;
+    type Output = Frame;
 
     fn create(link: AgentLink<Self>) -> Self {
         Self {
@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+
+use reqwasm::http::Request;
+use serde::Deserialize;
+
+const TENOR_SEARCH_URL: &str = "https://tenor.googleapis.com/v2/search";
+const RESULT_LIMIT: u32 = 24;
+
+#[derive(Clone, PartialEq)]
+pub struct Gif {
+    pub url: String,
+    pub preview: String,
+}
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    results: Vec<SearchResult>,
+}
+
+#[derive(Deserialize)]
+struct SearchResult {
+    media_formats: HashMap<String, Media>,
+}
+
+#[derive(Deserialize)]
+struct Media {
+    url: String,
+}
+
+pub async fn search(api_key: &str, query: &str) -> Result<Vec<Gif>, String> {
+    let url = format!(
+        "{}?q={}&key={}&limit={}&media_filter=gif,tinygif",
+        TENOR_SEARCH_URL,
+        js_sys::encode_uri_component(query),
+        js_sys::encode_uri_component(api_key),
+        RESULT_LIMIT
+    );
+    let response = Request::get(&url).send().await.map_err(|e| e.to_string())?;
+    if !response.ok() {
+        return Err(format!("GIF search failed ({})", response.status()));
+    }
+    let body: SearchResponse = response.json().await.map_err(|e| e.to_string())?;
+    Ok(body
+        .results
+        .into_iter()
+        .filter_map(|mut r| {
+            let url = r.media_formats.remove("gif")?.url;
+            let preview = r
+                .media_formats
+                .remove("tinygif")
+                .map(|m| m.url)
+                .unwrap_or_else(|| url.clone());
+            Some(Gif { url, preview })
+        })
+        .collect())
+}
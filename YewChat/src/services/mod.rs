@@ -1,2 +1,6 @@
 pub mod websocket;
-pub mod event_bus;
\ No newline at end of file
+pub mod event_bus;
+pub mod gif;
+pub mod platform;
+pub mod search;
+pub mod settings;
\ No newline at end of file
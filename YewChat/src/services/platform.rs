@@ -0,0 +1,52 @@
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{Notification, NotificationPermission};
+
+// Browser features that can be missing, or switched off outside a secure
+// context. Calling into them blindly throws, so check here first.
+
+fn has(target: &JsValue, name: &str) -> bool {
+    js_sys::Reflect::get(target, &JsValue::from_str(name))
+        .map(|value| !value.is_undefined() && !value.is_null())
+        .unwrap_or(false)
+}
+
+pub fn clipboard_available() -> bool {
+    let window = gloo::utils::window();
+    if !window.is_secure_context() {
+        return false;
+    }
+    js_sys::Reflect::get(&window.navigator(), &JsValue::from_str("clipboard"))
+        .map(|clipboard| has(&clipboard, "writeText"))
+        .unwrap_or(false)
+}
+
+pub fn notifications_available() -> bool {
+    has(&gloo::utils::window(), "Notification")
+}
+
+pub fn notifications_granted() -> bool {
+    notifications_available() && Notification::permission() == NotificationPermission::Granted
+}
+
+pub fn request_notifications() {
+    if notifications_available() && Notification::permission() != NotificationPermission::Granted {
+        let _ = Notification::request_permission();
+    }
+}
+
+// Goes through `Reflect` because web-sys only exposes the Clipboard API
+// behind `web_sys_unstable_apis`.
+pub async fn copy_to_clipboard(text: &str) -> Result<(), JsValue> {
+    if !clipboard_available() {
+        return Err(JsValue::from_str("clipboard unavailable"));
+    }
+    let navigator = gloo::utils::window().navigator();
+    let clipboard = js_sys::Reflect::get(&navigator, &JsValue::from_str("clipboard"))?;
+    let write_text: js_sys::Function =
+        js_sys::Reflect::get(&clipboard, &JsValue::from_str("writeText"))?.dyn_into()?;
+    let promise: js_sys::Promise = write_text
+        .call1(&clipboard, &JsValue::from_str(text))?
+        .dyn_into()?;
+    wasm_bindgen_futures::JsFuture::from(promise).await?;
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use std::collections::{HashMap, HashSet};
+
+#[derive(Default)]
+pub struct SearchIndex {
+    tokens: HashMap<String, HashSet<usize>>,
+}
+
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+}
+
+impl SearchIndex {
+    pub fn index_message(&mut self, index: usize, text: &str) {
+        for token in tokenize(text) {
+            self.tokens.entry(token).or_default().insert(index);
+        }
+    }
+
+    // Forgets `text` for `index`, e.g. the words an edit replaced.
+    pub fn remove_message(&mut self, index: usize, text: &str) {
+        for token in tokenize(text) {
+            if let Some(indices) = self.tokens.get_mut(&token) {
+                indices.remove(&index);
+                if indices.is_empty() {
+                    self.tokens.remove(&token);
+                }
+            }
+        }
+    }
+
+    // Every word of the query has to match; the last one only as a prefix
+    // since it is usually still being typed. `None` when the query has no
+    // words at all (e.g. only punctuation), meaning nothing is filtered.
+    pub fn query(&self, query: &str) -> Option<HashSet<usize>> {
+        let words: Vec<String> = tokenize(query).collect();
+        let mut result: Option<HashSet<usize>> = None;
+        for (i, word) in words.iter().enumerate() {
+            let matches: HashSet<usize> = if i + 1 == words.len() {
+                self.tokens
+                    .iter()
+                    .filter(|(token, _)| token.starts_with(word.as_str()))
+                    .flat_map(|(_, indices)| indices.iter().copied())
+                    .collect()
+            } else {
+                self.tokens.get(word).cloned().unwrap_or_default()
+            };
+            result = Some(match result {
+                Some(acc) => acc.intersection(&matches).copied().collect(),
+                None => matches,
+            });
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removed_words_no_longer_match() {
+        let mut index = SearchIndex::default();
+        index.index_message(0, "hello old world");
+        index.index_message(1, "old news");
+        index.remove_message(0, "hello old world");
+        index.index_message(0, "hello new world");
+        let old = index.query("old").unwrap();
+        assert!(old.contains(&1));
+        assert!(!old.contains(&0));
+        assert!(index.query("new").unwrap().contains(&0));
+        assert!(index.query("hello world").unwrap().contains(&0));
+    }
+
+    #[test]
+    fn queries_without_words_filter_nothing() {
+        let mut index = SearchIndex::default();
+        index.index_message(0, "hello");
+        assert_eq!(index.query("?"), None);
+        assert_eq!(index.query(" -- "), None);
+        assert_eq!(index.query(""), None);
+        assert_eq!(index.query("zzz"), Some(HashSet::new()));
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+
+use gloo::storage::{LocalStorage, Storage};
+use serde::{Deserialize, Serialize};
+
+const STORAGE_KEY: &str = "yewchat.settings";
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemeMode {
+    Dark,
+    Light,
+}
+
+// `Auto` leaves the choice to the browser locale.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClockFormat {
+    #[default]
+    Auto,
+    H12,
+    H24,
+}
+
+impl ClockFormat {
+    pub const ALL: [ClockFormat; 3] = [ClockFormat::Auto, ClockFormat::H12, ClockFormat::H24];
+
+    pub fn hour12(self) -> Option<bool> {
+        match self {
+            ClockFormat::Auto => None,
+            ClockFormat::H12 => Some(true),
+            ClockFormat::H24 => Some(false),
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ClockFormat::Auto => "Browser default",
+            ClockFormat::H12 => "12-hour",
+            ClockFormat::H24 => "24-hour",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AvatarShape {
+    #[default]
+    Circle,
+    Rounded,
+    Square,
+}
+
+impl AvatarShape {
+    pub const ALL: [AvatarShape; 3] = [AvatarShape::Circle, AvatarShape::Rounded, AvatarShape::Square];
+
+    pub fn class(self) -> &'static str {
+        match self {
+            AvatarShape::Circle => "rounded-full",
+            AvatarShape::Rounded => "rounded-lg",
+            AvatarShape::Square => "rounded-none",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            AvatarShape::Circle => "Circle",
+            AvatarShape::Rounded => "Rounded square",
+            AvatarShape::Square => "Square",
+        }
+    }
+}
+
+// The other combination (Shift+Enter or plain Enter) inserts a newline.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SendKey {
+    #[default]
+    Enter,
+    CtrlEnter,
+}
+
+impl SendKey {
+    pub const ALL: [SendKey; 2] = [SendKey::Enter, SendKey::CtrlEnter];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SendKey::Enter => "Enter",
+            SendKey::CtrlEnter => "Ctrl+Enter",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyLevel {
+    #[default]
+    All,
+    Mentions,
+    None,
+}
+
+impl NotifyLevel {
+    pub fn next(self) -> Self {
+        match self {
+            NotifyLevel::All => NotifyLevel::Mentions,
+            NotifyLevel::Mentions => NotifyLevel::None,
+            NotifyLevel::None => NotifyLevel::All,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SoundChoice {
+    // A label from `ChatConfig::sounds`; unknown labels play the default.
+    pub sound: String,
+    // 0 to 100.
+    pub volume: u8,
+}
+
+impl Default for SoundChoice {
+    fn default() -> Self {
+        Self {
+            sound: String::new(),
+            volume: 80,
+        }
+    }
+}
+
+// Missing fields fall back to their defaults so settings saved by an older
+// build still load.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Settings {
+    pub sound: bool,
+    pub message_sound: SoundChoice,
+    pub mention_sound: SoundChoice,
+    pub notifications: bool,
+    pub theme: ThemeMode,
+    pub compact: bool,
+    pub plain_text: bool,
+    // Images and videos wait for a click before anything is fetched.
+    pub media_links: bool,
+    // Swaps emoji for Twemoji images so they look the same everywhere.
+    pub twemoji: bool,
+    // On top of whatever `prefers-reduced-motion` already turns off.
+    pub reduce_motion: bool,
+    pub show_avatars: bool,
+    // Only matters for servers that list offline users.
+    pub show_offline: bool,
+    pub avatar_shape: AvatarShape,
+    pub collapse_repeats: bool,
+    pub timestamps_on_hover: bool,
+    pub clock: ClockFormat,
+    pub send_key: SendKey,
+    pub join_toasts: bool,
+    // Inline lines in the room when people join or leave.
+    pub join_lines: bool,
+    pub notify_everyone: bool,
+    // Debugging aid for schema mismatches between server and client.
+    pub show_unreadable: bool,
+    pub gif_api_key: String,
+    pub room_notify: HashMap<String, NotifyLevel>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            sound: false,
+            message_sound: SoundChoice::default(),
+            mention_sound: SoundChoice {
+                volume: 100,
+                ..SoundChoice::default()
+            },
+            notifications: false,
+            theme: ThemeMode::Dark,
+            compact: false,
+            plain_text: false,
+            media_links: false,
+            twemoji: false,
+            reduce_motion: false,
+            show_avatars: true,
+            show_offline: true,
+            avatar_shape: AvatarShape::Circle,
+            collapse_repeats: true,
+            timestamps_on_hover: false,
+            clock: ClockFormat::Auto,
+            send_key: SendKey::Enter,
+            join_toasts: true,
+            join_lines: false,
+            notify_everyone: false,
+            show_unreadable: false,
+            gif_api_key: String::new(),
+            room_notify: HashMap::new(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        LocalStorage::get(STORAGE_KEY).unwrap_or_default()
+    }
+
+    pub fn notify_level(&self, room: &str) -> NotifyLevel {
+        self.room_notify.get(room).copied().unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        if let Err(e) = LocalStorage::set(STORAGE_KEY, self) {
+            log::error!("failed to persist settings: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn join_toasts_and_join_lines_load_independently() {
+        let settings: Settings = serde_json::from_str(r#"{"join_toasts":false}"#).unwrap();
+        assert!(!settings.join_toasts);
+        assert!(!settings.join_lines);
+        let settings: Settings = serde_json::from_str(r#"{"join_lines":true}"#).unwrap();
+        assert!(settings.join_toasts);
+        assert!(settings.join_lines);
+    }
+}
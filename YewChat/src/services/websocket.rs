@@ -1,53 +1,467 @@
-use futures::{channel::mpsc::Sender, SinkExt, StreamExt};
-use reqwasm::websocket::{futures::WebSocket, Message};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+
+use futures::future::{self, abortable, AbortHandle, Either};
+use futures::{
+    channel::mpsc::{Receiver, Sender, UnboundedSender},
+    SinkExt, StreamExt,
+};
+use gloo::events::EventListener;
+use gloo::timers::callback::Interval;
+use gloo::timers::future::TimeoutFuture;
+use reqwasm::websocket::{futures::WebSocket, Message, State};
+use serde::Serialize;
 
 use wasm_bindgen_futures::spawn_local;
-use yew_agent::Dispatched;
+use yew::Callback;
+use yew_agent::{Dispatched, Dispatcher};
+
+use crate::services::event_bus::{EventBus, Frame, Request};
+
+pub const WS_URL: &str = "ws://127.0.0.1:8080";
+const RECONNECT_BASE_MS: u32 = 1_000;
+const RECONNECT_MAX_MS: u32 = 30_000;
+const OUTBOX_LIMIT: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectionState {
+    Connecting,
+    Open,
+    Reconnecting { attempt: u32, delay_ms: u32 },
+    // The very first attempt failed; nothing is retried automatically.
+    Failed,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WsError {
+    // The server could not be reached and nothing is being retried.
+    NotConnected,
+    // Too many frames are already waiting for the socket.
+    BufferFull,
+    SerializationFailed(String),
+    // The service has shut down.
+    Closed,
+}
+
+impl fmt::Display for WsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WsError::NotConnected => write!(f, "not connected"),
+            WsError::BufferFull => write!(f, "send buffer is full"),
+            WsError::SerializationFailed(e) => write!(f, "could not serialize frame: {}", e),
+            WsError::Closed => write!(f, "connection closed"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RawFrame {
+    pub outbound: bool,
+    pub time: f64,
+    pub data: String,
+}
+
+// The last few frames in either direction, kept only when debugging.
+struct Traffic {
+    frames: RefCell<VecDeque<RawFrame>>,
+    limit: usize,
+}
 
-use crate::services::event_bus::{EventBus, Request};
+impl Traffic {
+    fn record(&self, outbound: bool, data: &str) {
+        let mut frames = self.frames.borrow_mut();
+        if frames.len() == self.limit {
+            frames.pop_front();
+        }
+        frames.push_back(RawFrame {
+            outbound,
+            time: js_sys::Date::now(),
+            data: data.to_string(),
+        });
+    }
+}
+
+// The server drops frames from sockets that haven't registered, so frames
+// from the app wait here after every (re)connect until `greet` has sent the
+// registration.
+struct Outbox {
+    registered: bool,
+    held: VecDeque<String>,
+    limit: usize,
+}
+
+impl Outbox {
+    fn new(limit: usize) -> Self {
+        Self {
+            registered: false,
+            held: VecDeque::new(),
+            limit,
+        }
+    }
+
+    // Hands the frame back when it can go out right away.
+    fn offer(&mut self, frame: String) -> Result<Option<String>, WsError> {
+        if self.registered {
+            return Ok(Some(frame));
+        }
+        if self.held.len() >= self.limit {
+            return Err(WsError::BufferFull);
+        }
+        self.held.push_back(frame);
+        Ok(None)
+    }
+
+    fn release(&mut self) -> Vec<String> {
+        self.registered = true;
+        self.held.drain(..).collect()
+    }
+
+    // `unsent` never reached the old socket, so it goes out before anything
+    // held since.
+    fn reset(&mut self, unsent: Vec<String>) {
+        self.registered = false;
+        for frame in unsent.into_iter().rev() {
+            self.held.push_front(frame);
+        }
+    }
+}
+
+enum Disconnect {
+    Closed,
+    Shutdown,
+}
 
 pub struct WebsocketService {
-    pub tx: Sender<String>,
+    pub url: String,
+    tx: Sender<String>,
+    state: Rc<Cell<ConnectionState>>,
+    skip_backoff: UnboundedSender<()>,
+    task: AbortHandle,
+    outbox: Rc<RefCell<Outbox>>,
+    heartbeat: Option<Interval>,
+    traffic: Option<Rc<Traffic>>,
 }
 
-impl WebsocketService {
-    pub fn new() -> Self {
-        let ws = WebSocket::open("ws://127.0.0.1:8080").unwrap();
+fn backoff_delay(attempt: u32) -> u32 {
+    RECONNECT_BASE_MS
+        .saturating_mul(1 << attempt.saturating_sub(1).min(5))
+        .min(RECONNECT_MAX_MS)
+}
 
-        let (mut write, mut read) = ws.split();
+// "Equal jitter": keep half of the backoff and randomize the rest, so clients
+// that dropped together don't all come back in the same instant.
+fn jittered(delay_ms: u32, random: f64) -> u32 {
+    let half = delay_ms / 2;
+    half + (f64::from(delay_ms - half) * random.clamp(0.0, 1.0)) as u32
+}
 
-        let (in_tx, mut in_rx) = futures::channel::mpsc::channel::<String>(1000);
-        let mut event_bus = EventBus::dispatcher();
+fn is_online() -> bool {
+    gloo::utils::window().navigator().on_line()
+}
 
-        spawn_local(async move {
-            while let Some(s) = in_rx.next().await {
+// Resolves once the browser reports a network connection again.
+async fn online() {
+    if is_online() {
+        return;
+    }
+    let (tx, rx) = futures::channel::oneshot::channel();
+    let _listener = EventListener::once(&gloo::utils::window(), "online", move |_| {
+        let _ = tx.send(());
+    });
+    let _ = rx.await;
+}
+
+async fn connect(url: &str) -> Option<WebSocket> {
+    let ws = WebSocket::open(url).ok()?;
+    loop {
+        match ws.state() {
+            State::Connecting => TimeoutFuture::new(50).await,
+            State::Open => return Some(ws),
+            State::Closing | State::Closed => return None,
+        }
+    }
+}
+
+async fn run(
+    ws: WebSocket,
+    origin: &str,
+    in_rx: &mut Receiver<String>,
+    event_bus: &mut Dispatcher<EventBus>,
+    traffic: Option<&Traffic>,
+) -> Disconnect {
+    let (mut write, mut read) = ws.split();
+    loop {
+        match future::select(in_rx.next(), read.next()).await {
+            Either::Left((Some(s), _)) => {
                 log::debug!("got event from channel! {}", s);
-                write.send(Message::Text(s)).await.unwrap();
+                if let Some(traffic) = traffic {
+                    traffic.record(true, &s);
+                }
+                if let Err(e) = write.send(Message::Text(s)).await {
+                    log::error!("ws: {:?}", e);
+                    return Disconnect::Closed;
+                }
             }
+            Either::Left((None, _)) => return Disconnect::Shutdown,
+            Either::Right((Some(msg), _)) => match msg {
+                Ok(Message::Text(data)) => {
+                    log::debug!("from websocket: {}", data);
+                    if let Some(traffic) = traffic {
+                        traffic.record(false, &data);
+                    }
+                    event_bus.send(Request::EventBusMsg(Frame {
+                        origin: origin.into(),
+                        data,
+                    }));
+                }
+                Ok(Message::Bytes(b)) => {
+                    let decoded = std::str::from_utf8(&b);
+                    if let Ok(val) = decoded {
+                        log::debug!("from websocket: {}", val);
+                        if let Some(traffic) = traffic {
+                            traffic.record(false, val);
+                        }
+                        event_bus.send(Request::EventBusMsg(Frame {
+                            origin: origin.into(),
+                            data: val.into(),
+                        }));
+                    }
+                }
+                Err(e) => {
+                    log::error!("ws: {:?}", e)
+                }
+            },
+            Either::Right((None, _)) => {
+                log::debug!("WebSocket Closed");
+                return Disconnect::Closed;
+            }
+        }
+    }
+}
+
+impl WebsocketService {
+    // A non-zero `traffic_limit` keeps that many raw frames for `traffic()`.
+    pub fn new(url: &str, on_state: Callback<ConnectionState>, jitter: bool, traffic_limit: usize) -> Self {
+        let (in_tx, mut in_rx) = futures::channel::mpsc::channel::<String>(1000);
+        let (skip_tx, mut skip_rx) = futures::channel::mpsc::unbounded::<()>();
+        let mut event_bus = EventBus::dispatcher();
+        let origin = url.to_string();
+        let state = Rc::new(Cell::new(ConnectionState::Connecting));
+        let traffic = (traffic_limit > 0).then(|| {
+            Rc::new(Traffic {
+                frames: RefCell::new(VecDeque::with_capacity(traffic_limit)),
+                limit: traffic_limit,
+            })
         });
+        let task_traffic = traffic.clone();
+        let outbox = Rc::new(RefCell::new(Outbox::new(OUTBOX_LIMIT)));
+        let task_outbox = outbox.clone();
+        let on_state = {
+            let state = state.clone();
+            Callback::from(move |s| {
+                state.set(s);
+                on_state.emit(s);
+            })
+        };
 
-        spawn_local(async move {
-            while let Some(msg) = read.next().await {
-                match msg {
-                    Ok(Message::Text(data)) => {
-                        log::debug!("from websocket: {}", data);
-                        event_bus.send(Request::EventBusMsg(data));
-                    }
-                    Ok(Message::Bytes(b)) => {
-                        let decoded = std::str::from_utf8(&b);
-                        if let Ok(val) = decoded {
-                            log::debug!("from websocket: {}", val);
-                            event_bus.send(Request::EventBusMsg(val.into()));
+        // Frames sent while we are (re)connecting wait in the channel and go
+        // out once the socket is open again.
+        let (task, handle) = abortable(async move {
+            let mut attempt = 0;
+            let mut was_open = false;
+            on_state.emit(ConnectionState::Connecting);
+            loop {
+                // Retrying without a network only burns battery.
+                if !is_online() {
+                    on_state.emit(ConnectionState::Connecting);
+                    online().await;
+                    attempt = 0;
+                }
+                match connect(&origin).await {
+                    Some(ws) => {
+                        was_open = true;
+                        attempt = 0;
+                        on_state.emit(ConnectionState::Open);
+                        if let Disconnect::Shutdown = run(ws, &origin, &mut in_rx, &mut event_bus, task_traffic.as_deref()).await {
+                            return;
                         }
+                        let mut unsent = Vec::new();
+                        while let Ok(Some(frame)) = in_rx.try_next() {
+                            unsent.push(frame);
+                        }
+                        task_outbox.borrow_mut().reset(unsent);
                     }
-                    Err(e) => {
-                        log::error!("ws: {:?}", e)
+                    None if !was_open && is_online() => {
+                        on_state.emit(ConnectionState::Failed);
+                        return;
                     }
+                    None => {}
+                }
+                if !is_online() {
+                    continue;
+                }
+                attempt += 1;
+                let mut delay_ms = backoff_delay(attempt);
+                if jitter {
+                    delay_ms = jittered(delay_ms, js_sys::Math::random());
                 }
+                on_state.emit(ConnectionState::Reconnecting { attempt, delay_ms });
+                // Drop skips requested before this wait started.
+                while let Ok(Some(())) = skip_rx.try_next() {}
+                future::select(TimeoutFuture::new(delay_ms), skip_rx.next()).await;
             }
-            log::debug!("WebSocket Closed");
         });
+        spawn_local(async move {
+            let _ = task.await;
+        });
+
+        Self {
+            url: url.into(),
+            tx: in_tx,
+            state,
+            skip_backoff: skip_tx,
+            task: handle,
+            outbox,
+            heartbeat: None,
+            traffic,
+        }
+    }
+
+    // Frames sent while (re)connecting are held until the next `greet`, not
+    // rejected.
+    pub fn send(&self, frame: String) -> Result<(), WsError> {
+        if self.state.get() == ConnectionState::Failed {
+            return Err(WsError::NotConnected);
+        }
+        match self.outbox.borrow_mut().offer(frame)? {
+            Some(frame) => self.push(frame),
+            None => Ok(()),
+        }
+    }
+
+    // Sends `greeting` ahead of everything held since the socket opened, then
+    // lets the held frames follow. Call it on every `ConnectionState::Open`.
+    pub fn greet<T: Serialize>(&self, greeting: &[T]) -> Result<(), WsError> {
+        for message in greeting {
+            let frame = serde_json::to_string(message)
+                .map_err(|e| WsError::SerializationFailed(e.to_string()))?;
+            self.push(frame)?;
+        }
+        let held = self.outbox.borrow_mut().release();
+        held.into_iter().try_for_each(|frame| self.push(frame))
+    }
+
+    fn push(&self, frame: String) -> Result<(), WsError> {
+        self.tx.clone().try_send(frame).map_err(|e| {
+            if e.is_full() {
+                WsError::BufferFull
+            } else {
+                WsError::Closed
+            }
+        })
+    }
+
+    pub fn send_json<T: Serialize>(&self, message: &T) -> Result<(), WsError> {
+        let frame = serde_json::to_string(message)
+            .map_err(|e| WsError::SerializationFailed(e.to_string()))?;
+        self.send(frame)
+    }
+
+    // Oldest first; empty unless the service was created with a traffic limit.
+    pub fn traffic(&self) -> Vec<RawFrame> {
+        self.traffic
+            .as_ref()
+            .map(|t| t.frames.borrow().iter().cloned().collect())
+            .unwrap_or_default()
+    }
 
-        Self { tx: in_tx }
+    // Cuts the current backoff short; does nothing while connected.
+    pub fn reconnect_now(&self) {
+        let _ = self.skip_backoff.unbounded_send(());
+    }
+
+    pub fn start_heartbeat<F>(&mut self, interval_ms: u32, ping: F)
+    where
+        F: Fn() -> String + 'static,
+    {
+        let mut tx = self.tx.clone();
+        let state = self.state.clone();
+        self.heartbeat = Some(Interval::new(interval_ms, move || {
+            // Pings queued during an outage would all go out on reconnect
+            // with stale timestamps, and could fill the buffer.
+            if state.get() != ConnectionState::Open {
+                return;
+            }
+            if let Err(e) = tx.try_send(ping()) {
+                log::debug!("error sending heartbeat: {:?}", e);
+            }
+        }));
     }
-}
\ No newline at end of file
+}
+
+impl Drop for WebsocketService {
+    fn drop(&mut self) {
+        // Aborting the connection task drops the socket, which closes it, and
+        // stops any pending reconnect. Closing the channel makes any sender
+        // still around fail with `WsError::Closed` instead of queueing.
+        self.heartbeat = None;
+        self.task.abort();
+        self.tx.close_channel();
+        log::debug!("WebsocketService dropped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jitter_stays_between_half_and_full_delay() {
+        assert_eq!(jittered(8_000, 0.0), 4_000);
+        assert_eq!(jittered(8_000, 1.0), 8_000);
+        assert_eq!(jittered(8_001, 1.0), 8_001);
+        assert_eq!(jittered(8_000, 0.5), 6_000);
+    }
+
+    #[test]
+    fn jitter_clamps_out_of_range_randoms() {
+        assert_eq!(jittered(8_000, -3.0), 4_000);
+        assert_eq!(jittered(8_000, 7.0), 8_000);
+    }
+
+    #[test]
+    fn backoff_doubles_then_stops_at_the_cap() {
+        assert_eq!(backoff_delay(1), RECONNECT_BASE_MS);
+        assert_eq!(backoff_delay(2), 2 * RECONNECT_BASE_MS);
+        assert_eq!(backoff_delay(5), 16 * RECONNECT_BASE_MS);
+        assert_eq!(backoff_delay(6), RECONNECT_MAX_MS);
+        assert_eq!(backoff_delay(u32::MAX), RECONNECT_MAX_MS);
+    }
+
+    #[test]
+    fn outbox_holds_frames_until_released() {
+        let mut outbox = Outbox::new(10);
+        assert_eq!(outbox.offer("a".into()), Ok(None));
+        assert_eq!(outbox.offer("b".into()), Ok(None));
+        assert_eq!(outbox.release(), ["a", "b"]);
+        assert_eq!(outbox.offer("c".into()), Ok(Some("c".into())));
+    }
+
+    #[test]
+    fn outbox_resends_unsent_frames_first_after_a_drop() {
+        let mut outbox = Outbox::new(10);
+        outbox.release();
+        outbox.reset(vec!["a".into(), "b".into()]);
+        assert_eq!(outbox.offer("c".into()), Ok(None));
+        assert_eq!(outbox.release(), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn outbox_rejects_frames_past_its_limit() {
+        let mut outbox = Outbox::new(1);
+        assert_eq!(outbox.offer("a".into()), Ok(None));
+        assert_eq!(outbox.offer("b".into()), Err(WsError::BufferFull));
+    }
+}
@@ -0,0 +1,62 @@
+#[derive(Clone, Debug, PartialEq)]
+pub struct Theme {
+    pub background: String,
+    pub surface: String,
+    pub panel: String,
+    pub input: String,
+    pub accent: String,
+    pub border: String,
+    pub text: String,
+    pub muted: String,
+    pub highlight: String,
+}
+
+impl Theme {
+    pub fn light() -> Self {
+        Self {
+            background: "#f5f0eb".into(),
+            surface: "#ffffff".into(),
+            panel: "#e8e0d8".into(),
+            input: "#ffffff".into(),
+            accent: "#991b1b".into(),
+            border: "#d6bcbc".into(),
+            text: "#1f2937".into(),
+            muted: "#6b7280".into(),
+            highlight: "#b91c1c".into(),
+        }
+    }
+
+    pub fn style(&self) -> String {
+        [
+            ("--chat-bg", &self.background),
+            ("--chat-surface", &self.surface),
+            ("--chat-panel", &self.panel),
+            ("--chat-input", &self.input),
+            ("--chat-accent", &self.accent),
+            ("--chat-border", &self.border),
+            ("--chat-text", &self.text),
+            ("--chat-muted", &self.muted),
+            ("--chat-highlight", &self.highlight),
+        ]
+        .iter()
+        .map(|(var, value)| format!("{}: {};", var, value))
+        .collect::<Vec<_>>()
+        .join(" ")
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            background: "#121212".into(),
+            surface: "#1e1e1e".into(),
+            panel: "#000000".into(),
+            input: "#1a1a1a".into(),
+            accent: "#991b1b".into(),
+            border: "#7f1d1d".into(),
+            text: "#d1d5db".into(),
+            muted: "#9ca3af".into(),
+            highlight: "#ef4444".into(),
+        }
+    }
+}